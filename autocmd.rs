@@ -0,0 +1,309 @@
+use libc::c_int;
+
+use crate::event::{ emit, VimEvent };
+use crate::{
+    buf_T, event_T, string_from_vim, vimBufferGetFilename, vimBufferGetId,
+    vimSetAutoCommandCallback, vim_add_event_listener, EventListenerId
+};
+
+macro_rules! autocmd_events {
+    ($($name:ident = $raw:ident),* $(,)?) => {
+        /// The events `:autocmd` can listen to.
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+        pub enum AutoCmdEvent {
+            $($name),*
+        }
+
+        impl AutoCmdEvent {
+            pub const ALL: &'static [AutoCmdEvent] = &[$(AutoCmdEvent::$name),*];
+
+            fn from_raw(raw: event_T) -> Option<AutoCmdEvent> {
+                match raw {
+                    $(crate::$raw => Some(AutoCmdEvent::$name),)*
+                    _ => None
+                }
+            }
+
+            /// The event's name as written in `:autocmd`.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(AutoCmdEvent::$name => stringify!($name)),*
+                }
+            }
+        }
+    };
+}
+
+autocmd_events! {
+    BufAdd = auto_event_EVENT_BUFADD,
+    BufDelete = auto_event_EVENT_BUFDELETE,
+    BufEnter = auto_event_EVENT_BUFENTER,
+    BufFilePost = auto_event_EVENT_BUFFILEPOST,
+    BufFilePre = auto_event_EVENT_BUFFILEPRE,
+    BufHidden = auto_event_EVENT_BUFHIDDEN,
+    BufLeave = auto_event_EVENT_BUFLEAVE,
+    BufNew = auto_event_EVENT_BUFNEW,
+    BufNewFile = auto_event_EVENT_BUFNEWFILE,
+    BufReadCmd = auto_event_EVENT_BUFREADCMD,
+    BufReadPost = auto_event_EVENT_BUFREADPOST,
+    BufReadPre = auto_event_EVENT_BUFREADPRE,
+    BufUnload = auto_event_EVENT_BUFUNLOAD,
+    BufWinEnter = auto_event_EVENT_BUFWINENTER,
+    BufWinLeave = auto_event_EVENT_BUFWINLEAVE,
+    BufWipeout = auto_event_EVENT_BUFWIPEOUT,
+    BufWriteCmd = auto_event_EVENT_BUFWRITECMD,
+    BufWritePost = auto_event_EVENT_BUFWRITEPOST,
+    BufWritePre = auto_event_EVENT_BUFWRITEPRE,
+    CmdlineChanged = auto_event_EVENT_CMDLINECHANGED,
+    CmdlineEnter = auto_event_EVENT_CMDLINEENTER,
+    CmdlineLeave = auto_event_EVENT_CMDLINELEAVE,
+    CmdUndefined = auto_event_EVENT_CMDUNDEFINED,
+    CmdwinEnter = auto_event_EVENT_CMDWINENTER,
+    CmdwinLeave = auto_event_EVENT_CMDWINLEAVE,
+    ColorScheme = auto_event_EVENT_COLORSCHEME,
+    ColorSchemePre = auto_event_EVENT_COLORSCHEMEPRE,
+    CompleteChanged = auto_event_EVENT_COMPLETECHANGED,
+    CompleteDone = auto_event_EVENT_COMPLETEDONE,
+    CursorHold = auto_event_EVENT_CURSORHOLD,
+    CursorHoldI = auto_event_EVENT_CURSORHOLDI,
+    CursorMoved = auto_event_EVENT_CURSORMOVED,
+    CursorMovedI = auto_event_EVENT_CURSORMOVEDI,
+    DiffUpdated = auto_event_EVENT_DIFFUPDATED,
+    DirChanged = auto_event_EVENT_DIRCHANGED,
+    EncodingChanged = auto_event_EVENT_ENCODINGCHANGED,
+    ExitPre = auto_event_EVENT_EXITPRE,
+    FileAppendCmd = auto_event_EVENT_FILEAPPENDCMD,
+    FileAppendPost = auto_event_EVENT_FILEAPPENDPOST,
+    FileAppendPre = auto_event_EVENT_FILEAPPENDPRE,
+    FileChangedRO = auto_event_EVENT_FILECHANGEDRO,
+    FileChangedShell = auto_event_EVENT_FILECHANGEDSHELL,
+    FileChangedShellPost = auto_event_EVENT_FILECHANGEDSHELLPOST,
+    FileReadCmd = auto_event_EVENT_FILEREADCMD,
+    FileReadPost = auto_event_EVENT_FILEREADPOST,
+    FileReadPre = auto_event_EVENT_FILEREADPRE,
+    FileType = auto_event_EVENT_FILETYPE,
+    FileWriteCmd = auto_event_EVENT_FILEWRITECMD,
+    FileWritePost = auto_event_EVENT_FILEWRITEPOST,
+    FileWritePre = auto_event_EVENT_FILEWRITEPRE,
+    FilterReadPost = auto_event_EVENT_FILTERREADPOST,
+    FilterReadPre = auto_event_EVENT_FILTERREADPRE,
+    FilterWritePost = auto_event_EVENT_FILTERWRITEPOST,
+    FilterWritePre = auto_event_EVENT_FILTERWRITEPRE,
+    FocusGained = auto_event_EVENT_FOCUSGAINED,
+    FocusLost = auto_event_EVENT_FOCUSLOST,
+    FuncUndefined = auto_event_EVENT_FUNCUNDEFINED,
+    GUIEnter = auto_event_EVENT_GUIENTER,
+    GUIFailed = auto_event_EVENT_GUIFAILED,
+    InsertChange = auto_event_EVENT_INSERTCHANGE,
+    InsertCharPre = auto_event_EVENT_INSERTCHARPRE,
+    InsertEnter = auto_event_EVENT_INSERTENTER,
+    InsertLeave = auto_event_EVENT_INSERTLEAVE,
+    MenuPopup = auto_event_EVENT_MENUPOPUP,
+    OptionSet = auto_event_EVENT_OPTIONSET,
+    QuickFixCmdPost = auto_event_EVENT_QUICKFIXCMDPOST,
+    QuickFixCmdPre = auto_event_EVENT_QUICKFIXCMDPRE,
+    QuitPre = auto_event_EVENT_QUITPRE,
+    RemoteReply = auto_event_EVENT_REMOTEREPLY,
+    SessionLoadPost = auto_event_EVENT_SESSIONLOADPOST,
+    ShellCmdPost = auto_event_EVENT_SHELLCMDPOST,
+    ShellFilterPost = auto_event_EVENT_SHELLFILTERPOST,
+    SourceCmd = auto_event_EVENT_SOURCECMD,
+    SourcePre = auto_event_EVENT_SOURCEPRE,
+    SourcePost = auto_event_EVENT_SOURCEPOST,
+    SpellFileMissing = auto_event_EVENT_SPELLFILEMISSING,
+    StdinReadPost = auto_event_EVENT_STDINREADPOST,
+    StdinReadPre = auto_event_EVENT_STDINREADPRE,
+    SwapExists = auto_event_EVENT_SWAPEXISTS,
+    Syntax = auto_event_EVENT_SYNTAX,
+    TabClosed = auto_event_EVENT_TABCLOSED,
+    TabEnter = auto_event_EVENT_TABENTER,
+    TabLeave = auto_event_EVENT_TABLEAVE,
+    TabNew = auto_event_EVENT_TABNEW,
+    TermChanged = auto_event_EVENT_TERMCHANGED,
+    TerminalOpen = auto_event_EVENT_TERMINALOPEN,
+    TermResponse = auto_event_EVENT_TERMRESPONSE,
+    TextChanged = auto_event_EVENT_TEXTCHANGED,
+    TextChangedI = auto_event_EVENT_TEXTCHANGEDI,
+    TextChangedP = auto_event_EVENT_TEXTCHANGEDP,
+    TextYankPost = auto_event_EVENT_TEXTYANKPOST,
+    User = auto_event_EVENT_USER,
+    VimEnter = auto_event_EVENT_VIMENTER,
+    VimLeave = auto_event_EVENT_VIMLEAVE,
+    VimLeavePre = auto_event_EVENT_VIMLEAVEPRE,
+    VimResized = auto_event_EVENT_VIMRESIZED,
+    WinEnter = auto_event_EVENT_WINENTER,
+    WinLeave = auto_event_EVENT_WINLEAVE,
+    WinNew = auto_event_EVENT_WINNEW
+}
+
+/// An autocommand event as it fired, with the buffer it fired for.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AutoCmd {
+    pub event: AutoCmdEvent,
+    pub buffer_id: c_int,
+    pub filename: Option<String>
+}
+
+pub(crate) fn install_callbacks() {
+    unsafe { vimSetAutoCommandCallback(Some(auto_command)); }
+}
+
+unsafe extern "C" fn auto_command(event: event_T, buf: *mut buf_T) {
+    if let Some(event) = AutoCmdEvent::from_raw(event) {
+        emit(VimEvent::AutoCommand(AutoCmd {
+            event,
+            buffer_id: if buf.is_null() { 0 } else { vimBufferGetId(buf) },
+            filename: if buf.is_null() { None } else { string_from_vim(vimBufferGetFilename(buf)) }
+        }));
+    }
+}
+
+/// Calls `handler` whenever `event` fires for a buffer whose name matches
+/// `pattern`, an `:autocmd` style file pattern such as `*.rs` or
+/// `*.{c,h},Makefile`. Without a pattern every occurrence is passed on.
+/// Remove the subscription with `vim_remove_event_listener`.
+pub fn vim_on_autocmd<F: FnMut(&AutoCmd) + 'static>(event: AutoCmdEvent, pattern: Option<&str>, mut handler: F) -> EventListenerId {
+    let pattern = pattern.map(String::from);
+    vim_add_event_listener(move |vim_event| {
+        if let VimEvent::AutoCommand(autocmd) = vim_event {
+            let matches = match (&pattern, &autocmd.filename) {
+                (None, _) => true,
+                (Some(pattern), Some(filename)) => pattern_matches(pattern, filename),
+                (Some(pattern), None) => pattern == "*"
+            };
+            if autocmd.event == event && matches {
+                handler(autocmd);
+            }
+        }
+    })
+}
+
+/// Whether `filename` matches an autocommand pattern. Like in Vim, a pattern
+/// without a `/` is only matched against the last path component.
+fn pattern_matches(pattern: &str, filename: &str) -> bool {
+    split_top_level(pattern).iter().any(|pattern| {
+        let name = if pattern.contains('/') {
+            filename
+        } else {
+            filename.rsplit('/').next().unwrap_or(filename)
+        };
+        let name: Vec<char> = name.chars().collect();
+        expand_braces(pattern).iter().any(|pattern| {
+            let pattern: Vec<char> = pattern.chars().collect();
+            glob_matches(&pattern, &name)
+        })
+    })
+}
+
+// Splits on the commas that are not inside braces or escaped.
+fn split_top_level(pattern: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut depth = 0;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                parts.last_mut().unwrap().push(c);
+                parts.last_mut().unwrap().extend(chars.next());
+                continue;
+            },
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(String::new());
+                continue;
+            },
+            _ => {}
+        }
+        parts.last_mut().unwrap().push(c);
+    }
+    parts
+}
+
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let open = match pattern.find('{') {
+        Some(open) => open,
+        None => return vec![pattern.to_string()]
+    };
+    let mut depth = 0;
+    let close = pattern[open..].char_indices().find_map(|(index, c)| {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 { Some(open + index) } else { None }
+    });
+    match close {
+        Some(close) => split_top_level(&pattern[open + 1..close]).iter()
+            .flat_map(|alternative| expand_braces(&format!("{}{}{}", &pattern[..open], alternative, &pattern[close + 1..])))
+            .collect(),
+        None => vec![pattern.to_string()]
+    }
+}
+
+fn glob_matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| glob_matches(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && glob_matches(&pattern[1..], &name[1..]),
+        Some('\\') if pattern.len() > 1 => {
+            name.first() == Some(&pattern[1]) && glob_matches(&pattern[2..], &name[1..])
+        },
+        Some('[') => match pattern.iter().skip(2).position(|c| *c == ']') {
+            Some(end) => {
+                let class = &pattern[1..end + 2];
+                let (negated, class) = match class.first() {
+                    Some('^' | '!') => (true, &class[1..]),
+                    _ => (false, class)
+                };
+                match name.first() {
+                    Some(c) if class_contains(class, *c) != negated => glob_matches(&pattern[end + 3..], &name[1..]),
+                    _ => false
+                }
+            },
+            None => name.first() == Some(&'[') && glob_matches(&pattern[1..], &name[1..])
+        },
+        Some(c) => name.first() == Some(c) && glob_matches(&pattern[1..], &name[1..])
+    }
+}
+
+fn class_contains(class: &[char], c: char) -> bool {
+    let mut index = 0;
+    while index < class.len() {
+        if index + 2 < class.len() && class[index + 1] == '-' {
+            if (class[index]..=class[index + 2]).contains(&c) {
+                return true;
+            }
+            index += 3;
+        } else {
+            if class[index] == c {
+                return true;
+            }
+            index += 1;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_matches_test() {
+        assert!(pattern_matches("*.rs", "/home/fry/src/lib.rs"));
+        assert!(!pattern_matches("*.rs", "/home/fry/src/lib.rs.orig"));
+        assert!(pattern_matches("*.{c,h},Makefile", "/src/planet/express.h"));
+        assert!(pattern_matches("*.{c,h},Makefile", "/src/planet/Makefile"));
+        assert!(pattern_matches("*/test/*.rs", "/src/crate/test/feed.rs"));
+        assert!(!pattern_matches("*/test/*.rs", "/src/crate/feed.rs"));
+        assert!(pattern_matches("file[0-9].txt", "file7.txt"));
+        assert!(!pattern_matches("file[^0-9].txt", "file7.txt"));
+        assert!(pattern_matches("?akefile", "makefile"));
+
+        assert_eq!(AutoCmdEvent::from_raw(crate::auto_event_EVENT_BUFWRITEPOST), Some(AutoCmdEvent::BufWritePost));
+        assert_eq!(AutoCmdEvent::InsertLeave.name(), "InsertLeave");
+        assert_eq!(AutoCmdEvent::ALL.len(), 99);
+    }
+}
@@ -0,0 +1,211 @@
+use libc::c_int;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::io::Write;
+use std::process::{ Command, Stdio };
+use std::rc::Rc;
+
+use crate::event::HandlerSlot;
+use crate::{
+    char_u, lines_from_vim, lines_to_vim, vimSetClipboardGetCallback, vimSetYankCallback,
+    yankInfo_T, Register, RegisterKind
+};
+
+/// Backs the `"+` and `"*` registers. `get` returning `None` leaves the
+/// register to libvim.
+pub trait ClipboardProvider {
+    fn get(&mut self, register: char) -> Option<Register>;
+    fn set(&mut self, register: char, contents: &Register);
+}
+
+thread_local! {
+    static PROVIDER: HandlerSlot<dyn ClipboardProvider> = const { HandlerSlot::new() };
+}
+
+pub fn vim_set_clipboard_provider<P: ClipboardProvider + 'static>(provider: P) {
+    PROVIDER.with(|slot| slot.set(Some(Box::new(provider))));
+    unsafe {
+        vimSetClipboardGetCallback(Some(clipboard_get));
+        vimSetYankCallback(Some(clipboard_yank));
+    }
+}
+
+pub fn vim_clear_clipboard_provider() {
+    PROVIDER.with(|slot| slot.set(None));
+    unsafe {
+        vimSetClipboardGetCallback(None);
+        vimSetYankCallback(None);
+    }
+}
+
+fn clipboard_register(regname: c_int) -> Option<char> {
+    match char::from_u32(regname as u32) {
+        Some(register @ ('+' | '*')) => Some(register),
+        _ => None
+    }
+}
+
+unsafe extern "C" fn clipboard_get(
+    regname: c_int,
+    num_lines: *mut c_int,
+    lines: *mut *mut *mut char_u,
+    block_type: *mut c_int
+) -> c_int {
+    let register = match clipboard_register(regname) {
+        Some(register) => register,
+        None => return 0
+    };
+    match PROVIDER.with(|slot| slot.call(|provider| provider.get(register))) {
+        Some(Some(contents)) => {
+            *num_lines = contents.lines.len() as c_int;
+            *lines = lines_to_vim(&contents.lines);
+            *block_type = contents.kind.to_raw();
+            1
+        },
+        _ => 0
+    }
+}
+
+unsafe extern "C" fn clipboard_yank(yank_info: *mut yankInfo_T) {
+    let yank_info = &*yank_info;
+    if let Some(register) = clipboard_register(yank_info.regname) {
+        let contents = Register::new(
+            lines_from_vim(yank_info.lines, yank_info.numLines),
+            RegisterKind::from_raw(yank_info.blockType)
+        );
+        PROVIDER.with(|slot| slot.call(|provider| provider.set(register, &contents)));
+    }
+}
+
+/// Keeps clipboard contents in memory. Clones share the same storage, so a
+/// test can hand one to libvim and inspect the other.
+#[derive(Clone, Default)]
+pub struct MemoryClipboard {
+    registers: Rc<RefCell<HashMap<char, Register>>>
+}
+
+impl MemoryClipboard {
+    pub fn new() -> MemoryClipboard {
+        MemoryClipboard::default()
+    }
+
+    pub fn contents(&self, register: char) -> Option<Register> {
+        self.registers.borrow().get(&register).cloned()
+    }
+
+    pub fn set_contents(&self, register: char, contents: Register) {
+        self.registers.borrow_mut().insert(register, contents);
+    }
+}
+
+impl ClipboardProvider for MemoryClipboard {
+    fn get(&mut self, register: char) -> Option<Register> {
+        self.contents(register)
+    }
+
+    fn set(&mut self, register: char, contents: &Register) {
+        self.set_contents(register, contents.clone());
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ClipboardTool {
+    WlClipboard,
+    Xclip,
+    Pasteboard
+}
+
+/// Talks to the desktop clipboard through `wl-copy`/`wl-paste`, `xclip` or
+/// `pbcopy`/`pbpaste`, whichever is installed. `"*` maps to the primary
+/// selection where the platform has one.
+#[derive(Clone, Debug)]
+pub struct SystemClipboard {
+    tool: ClipboardTool
+}
+
+impl SystemClipboard {
+    pub fn detect() -> Option<SystemClipboard> {
+        let tool = if env::var_os("WAYLAND_DISPLAY").is_some() && command_exists("wl-copy") && command_exists("wl-paste") {
+            ClipboardTool::WlClipboard
+        } else if command_exists("xclip") {
+            ClipboardTool::Xclip
+        } else if command_exists("pbcopy") && command_exists("pbpaste") {
+            ClipboardTool::Pasteboard
+        } else {
+            return None;
+        };
+        Some(SystemClipboard { tool })
+    }
+
+    fn paste_command(&self, register: char) -> Command {
+        match self.tool {
+            ClipboardTool::WlClipboard => {
+                let mut command = Command::new("wl-paste");
+                command.arg("--no-newline");
+                if register == '*' {
+                    command.arg("--primary");
+                }
+                command
+            },
+            ClipboardTool::Xclip => {
+                let mut command = Command::new("xclip");
+                command.args(["-selection", xclip_selection(register), "-o"]);
+                command
+            },
+            ClipboardTool::Pasteboard => Command::new("pbpaste")
+        }
+    }
+
+    fn copy_command(&self, register: char) -> Command {
+        match self.tool {
+            ClipboardTool::WlClipboard => {
+                let mut command = Command::new("wl-copy");
+                if register == '*' {
+                    command.arg("--primary");
+                }
+                command
+            },
+            ClipboardTool::Xclip => {
+                let mut command = Command::new("xclip");
+                command.args(["-selection", xclip_selection(register), "-i"]);
+                command
+            },
+            ClipboardTool::Pasteboard => Command::new("pbcopy")
+        }
+    }
+}
+
+impl ClipboardProvider for SystemClipboard {
+    fn get(&mut self, register: char) -> Option<Register> {
+        let output = self.paste_command(register).stderr(Stdio::null()).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(Register::from_text(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn set(&mut self, register: char, contents: &Register) {
+        let child = self.copy_command(register)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if let Ok(mut child) = child {
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(contents.to_text().as_bytes());
+            }
+            let _ = child.wait();
+        }
+    }
+}
+
+fn xclip_selection(register: char) -> &'static str {
+    if register == '*' { "primary" } else { "clipboard" }
+}
+
+fn command_exists(name: &str) -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(name).is_file()))
+        .unwrap_or(false)
+}
@@ -0,0 +1,394 @@
+use libc::{ c_char, c_int, c_long };
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::rc::Rc;
+
+use crate::event::HandlerSlot;
+use crate::{
+    char_u, emsg, eval_string, exCommand_T, lines_from_vim, string_from_vim, vimBufferGetCurrent,
+    vimBufferGetLineCount, vimCommandLineGetCompletions, vimCommandLineGetPosition,
+    vimCommandLineGetText, vimCommandLineGetType, vimCursorGetLine, vimSetCustomCommandHandler, FreeWild
+};
+
+/// The lines a command was given, already resolved to line numbers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CommandRange {
+    pub start: c_long,
+    pub end: c_long
+}
+
+/// An invocation of a command registered with `vim_register_command`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CommandArgs {
+    pub name: String,
+    pub bang: bool,
+    pub range: Option<CommandRange>,
+    pub register: Option<char>,
+    pub args: String
+}
+
+impl CommandArgs {
+    /// The arguments split on white space, like `<f-args>`. A backslash
+    /// keeps the following space in the current argument.
+    pub fn fargs(&self) -> Vec<String> {
+        let mut fargs = Vec::new();
+        let mut current = String::new();
+        let mut chars = self.args.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek().is_some_and(|next| next.is_whitespace() || *next == '\\') => {
+                    current.extend(chars.next());
+                },
+                c if c.is_whitespace() => {
+                    if !current.is_empty() {
+                        fargs.push(std::mem::take(&mut current));
+                    }
+                },
+                c => current.push(c)
+            }
+        }
+        if !current.is_empty() {
+            fargs.push(current);
+        }
+        fargs
+    }
+}
+
+type CommandHandler = dyn FnMut(&CommandArgs) -> Result<(), String>;
+type CommandCompleter = dyn FnMut(&str) -> Vec<String>;
+
+/// An Ex command implemented by the host. Returning `Err` from the handler
+/// reports the message as a Vim error.
+pub struct UserCommand {
+    name: String,
+    handler: Box<CommandHandler>,
+    register: bool,
+    internal: bool,
+    completer: Option<Box<CommandCompleter>>
+}
+
+impl UserCommand {
+    pub fn new<F: FnMut(&CommandArgs) -> Result<(), String> + 'static>(name: &str, handler: F) -> UserCommand {
+        UserCommand {
+            name: name.to_string(),
+            handler: Box::new(handler),
+            register: false,
+            internal: false,
+            completer: None
+        }
+    }
+
+    /// Takes an optional register name as the first argument, like
+    /// `-register` on `:command`.
+    pub fn with_register(mut self) -> UserCommand {
+        self.register = true;
+        self
+    }
+
+    /// Completes the arguments typed so far on the command line.
+    pub fn with_completion<F: FnMut(&str) -> Vec<String> + 'static>(mut self, completer: F) -> UserCommand {
+        self.completer = Some(Box::new(completer));
+        self
+    }
+
+    // Commands the crate runs itself, such as the ones behind `vim_map`. They
+    // are not completed, and only run when given their full name.
+    pub(crate) fn internal(mut self) -> UserCommand {
+        self.internal = true;
+        self
+    }
+}
+
+struct RegisteredCommand {
+    name: String,
+    register: bool,
+    internal: bool,
+    handler: HandlerSlot<CommandHandler>,
+    completer: HandlerSlot<CommandCompleter>
+}
+
+thread_local! {
+    static COMMANDS: RefCell<Vec<Rc<RegisteredCommand>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Registers `command`, replacing any command of the same name. Like Vim's
+/// own user commands the name has to start with an uppercase letter and
+/// may only contain letters and digits; returns false if it does not.
+pub fn vim_register_command(command: UserCommand) -> bool {
+    let valid = command.name.chars().next().is_some_and(|c| c.is_ascii_uppercase())
+        && command.name.chars().all(|c| c.is_ascii_alphanumeric());
+    if !valid {
+        return false;
+    }
+    let registered = RegisteredCommand {
+        name: command.name,
+        register: command.register,
+        internal: command.internal,
+        handler: HandlerSlot::new(),
+        completer: HandlerSlot::new()
+    };
+    registered.handler.set(Some(command.handler));
+    registered.completer.set(command.completer);
+    COMMANDS.with(|commands| {
+        let mut commands = commands.borrow_mut();
+        commands.retain(|existing| existing.name != registered.name);
+        commands.push(Rc::new(registered));
+    });
+    unsafe { vimSetCustomCommandHandler(Some(custom_command)); }
+    true
+}
+
+pub fn vim_unregister_command(name: &str) -> bool {
+    COMMANDS.with(|commands| {
+        let mut commands = commands.borrow_mut();
+        let count = commands.len();
+        commands.retain(|existing| existing.name != name);
+        if commands.is_empty() {
+            unsafe { vimSetCustomCommandHandler(None); }
+        }
+        commands.len() != count
+    })
+}
+
+/// The completions for the command line being typed: libvim's own, plus the
+/// registered command names and the arguments their completers offer.
+pub fn vim_command_line_completions() -> Vec<String> {
+    let mut completions = unsafe {
+        let mut raw: *mut *mut char_u = std::ptr::null_mut();
+        let mut count: c_int = 0;
+        vimCommandLineGetCompletions(&mut raw, &mut count);
+        let completions = lines_from_vim(raw, count);
+        if !raw.is_null() {
+            FreeWild(count, raw);
+        }
+        completions
+    };
+    if unsafe { vimCommandLineGetType() } != b':' {
+        return completions;
+    }
+    let text = unsafe { string_from_vim(vimCommandLineGetText()) }.unwrap_or_default();
+    let position = (unsafe { vimCommandLineGetPosition() }.max(0) as usize).min(text.len());
+    let parsed = match text.get(..position).and_then(parse_command) {
+        Some(parsed) => parsed,
+        None => return completions
+    };
+    match parsed.args {
+        None if !parsed.bang => {
+            let names = COMMANDS.with(|commands| commands.borrow().iter()
+                .filter(|command| !command.internal && command.name.starts_with(parsed.name))
+                .map(|command| command.name.clone())
+                .collect::<Vec<_>>());
+            completions.extend(names);
+            completions.sort();
+            completions.dedup();
+            completions
+        },
+        args => match find_command(parsed.name) {
+            Some(command) => command.completer
+                .call(|completer| completer(args.unwrap_or_default()))
+                .unwrap_or(completions),
+            None => completions
+        }
+    }
+}
+
+// Exact names win, otherwise an unambiguous prefix is accepted like Vim does.
+fn find_command(name: &str) -> Option<Rc<RegisteredCommand>> {
+    if name.is_empty() {
+        return None;
+    }
+    COMMANDS.with(|commands| {
+        let commands = commands.borrow();
+        if let Some(command) = commands.iter().find(|command| command.name == name) {
+            return Some(command.clone());
+        }
+        let mut matches = commands.iter().filter(|command| !command.internal && command.name.starts_with(name));
+        match (matches.next(), matches.next()) {
+            (Some(command), None) => Some(command.clone()),
+            _ => None
+        }
+    })
+}
+
+unsafe extern "C" fn custom_command(eap: *mut exCommand_T) -> c_int {
+    if eap.is_null() {
+        return 0;
+    }
+    let text = string_from_vim((*eap).cmd).unwrap_or_default();
+    let parsed = match parse_command(&text) {
+        Some(parsed) => parsed,
+        None => return 0
+    };
+    let command = match find_command(parsed.name) {
+        Some(command) => command,
+        None => return 0
+    };
+
+    let mut args = parsed.args.unwrap_or_default().trim_start();
+    let mut register = match (*eap).regname {
+        0 => None,
+        regname => char::from_u32(regname as u32)
+    };
+    if command.register && register.is_none() {
+        if let Some(c) = args.chars().next().filter(|c| is_register_name(*c)) {
+            register = Some(c);
+            args = args[c.len_utf8()..].trim_start();
+        }
+    }
+    let args = CommandArgs {
+        name: command.name.clone(),
+        bang: parsed.bang || (*eap).forceit != 0,
+        range: parsed.range.map(|(start, end)| CommandRange { start: resolve(start), end: resolve(end) }),
+        register,
+        args: args.to_string()
+    };
+    let result = command.handler.call(|handler| handler(&args))
+        .unwrap_or_else(|| Err(format!("{} is already running", command.name)));
+    if let Err(message) = result {
+        let message = CString::new(message.replace('\0', " ")).unwrap();
+        emsg(message.as_ptr() as *mut c_char);
+    }
+    1
+}
+
+fn is_register_name(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "\"-*+_/:.%#".contains(c)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Address {
+    Line(c_long),
+    Current,
+    Last,
+    Mark(char)
+}
+
+type OffsetAddress = (Address, c_long);
+
+#[derive(PartialEq, Eq, Debug)]
+struct ParsedCommand<'a> {
+    range: Option<(OffsetAddress, OffsetAddress)>,
+    name: &'a str,
+    bang: bool,
+    /// `None` when nothing, not even white space, follows the name.
+    args: Option<&'a str>
+}
+
+fn parse_command(text: &str) -> Option<ParsedCommand<'_>> {
+    let mut rest = text.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+    let range = if let Some(after) = rest.strip_prefix('%') {
+        rest = after;
+        Some(((Address::Line(1), 0), (Address::Last, 0)))
+    } else {
+        match parse_address(&mut rest) {
+            Some(start) => {
+                let end = match rest.strip_prefix([',', ';']) {
+                    Some(after) => {
+                        rest = after;
+                        parse_address(&mut rest).unwrap_or(start)
+                    },
+                    None => start
+                };
+                Some((start, end))
+            },
+            None => None
+        }
+    };
+    rest = rest.trim_start();
+
+    let name_length = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+    let (name, mut rest) = rest.split_at(name_length);
+    if name.is_empty() && !rest.is_empty() {
+        return None;
+    }
+    let bang = match rest.strip_prefix('!') {
+        Some(after) => {
+            rest = after;
+            true
+        },
+        None => false
+    };
+    let args = if rest.is_empty() { None } else { Some(rest.trim_start()) };
+    Some(ParsedCommand { range, name, bang, args })
+}
+
+fn parse_address(text: &mut &str) -> Option<OffsetAddress> {
+    let rest = *text;
+    let (address, mut rest) = if let Some(after) = rest.strip_prefix('.') {
+        (Some(Address::Current), after)
+    } else if let Some(after) = rest.strip_prefix('$') {
+        (Some(Address::Last), after)
+    } else if let Some(after) = rest.strip_prefix('\'') {
+        let mark = after.chars().next()?;
+        (Some(Address::Mark(mark)), &after[mark.len_utf8()..])
+    } else {
+        match take_number(rest) {
+            (Some(line), after) => (Some(Address::Line(line)), after),
+            (None, _) => (None, rest)
+        }
+    };
+
+    let mut offset = 0;
+    while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let (amount, after) = take_number(&rest[1..]);
+        let amount = amount.unwrap_or(1);
+        offset += if sign == '+' { amount } else { -amount };
+        rest = after;
+    }
+    if address.is_none() && offset == 0 {
+        return None;
+    }
+    *text = rest;
+    Some((address.unwrap_or(Address::Current), offset))
+}
+
+fn take_number(text: &str) -> (Option<c_long>, &str) {
+    let length = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (digits, rest) = text.split_at(length);
+    (digits.parse().ok(), rest)
+}
+
+fn resolve((address, offset): OffsetAddress) -> c_long {
+    let line = match address {
+        Address::Line(line) => line,
+        Address::Current => unsafe { vimCursorGetLine() },
+        Address::Last => unsafe { vimBufferGetLineCount(vimBufferGetCurrent()) as c_long },
+        Address::Mark(mark) => eval_string(&format!("line(\"'{}\")", mark))
+            .and_then(|line| line.parse().ok())
+            .unwrap_or(0)
+    };
+    line + offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_command_test() {
+        let parsed = parse_command(":Format").expect("Parse Format");
+        assert_eq!(parsed, ParsedCommand { range: None, name: "Format", bang: false, args: None });
+
+        let parsed = parse_command("%Deploy! staging  now").expect("Parse Deploy");
+        assert_eq!(parsed.range, Some(((Address::Line(1), 0), (Address::Last, 0))));
+        assert_eq!((parsed.name, parsed.bang, parsed.args), ("Deploy", true, Some("staging  now")));
+
+        let parsed = parse_command("'<,'>Test ").expect("Parse Test");
+        assert_eq!(parsed.range, Some(((Address::Mark('<'), 0), (Address::Mark('>'), 0))));
+        assert_eq!(parsed.args, Some(""));
+
+        let parsed = parse_command("3,.+2Sort").expect("Parse Sort");
+        assert_eq!(parsed.range, Some(((Address::Line(3), 0), (Address::Current, 2))));
+
+        assert_eq!(parse_command("!ls"), None);
+
+        let args = CommandArgs {
+            name: "Test".to_string(),
+            bang: false,
+            range: None,
+            register: None,
+            args: r"unit my\ file  --quiet".to_string()
+        };
+        assert_eq!(args.fargs(), vec!["unit", "my file", "--quiet"]);
+    }
+}
@@ -0,0 +1,231 @@
+use libc::{ c_int, c_long };
+use std::collections::HashMap;
+
+use crate::event::HandlerSlot;
+use crate::{
+    buf_T, buffer_line, char_u, lines_to_vim, linenr_T, vimBufferGetId, vimSetToggleCommentsCallback,
+    vim_buffer_get_filetype
+};
+
+/// The lines a `gc` command wants to toggle.
+#[derive(Clone, Debug)]
+pub struct CommentRequest {
+    pub buffer_id: c_int,
+    pub filetype: Option<String>,
+    pub start_line: c_long,
+    pub end_line: c_long,
+    pub lines: Vec<String>
+}
+
+pub trait CommentProvider {
+    /// Returns the lines replacing `request.lines`, or `None` to leave them
+    /// untouched.
+    fn toggle(&mut self, request: &CommentRequest) -> Option<Vec<String>>;
+}
+
+impl<F: FnMut(&CommentRequest) -> Option<Vec<String>>> CommentProvider for F {
+    fn toggle(&mut self, request: &CommentRequest) -> Option<Vec<String>> {
+        self(request)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CommentTokens {
+    pub line: Option<String>,
+    pub block: Option<(String, String)>
+}
+
+impl CommentTokens {
+    pub fn line(token: &str) -> CommentTokens {
+        CommentTokens { line: Some(token.to_string()), block: None }
+    }
+
+    pub fn block(start: &str, end: &str) -> CommentTokens {
+        CommentTokens { line: None, block: Some((start.to_string(), end.to_string())) }
+    }
+
+    pub fn line_and_block(token: &str, start: &str, end: &str) -> CommentTokens {
+        CommentTokens { line: Some(token.to_string()), block: Some((start.to_string(), end.to_string())) }
+    }
+}
+
+/// Toggles comments the way vim-commentary does: if every non-blank line is
+/// already commented the comments are removed, otherwise all non-blank lines
+/// are commented at the smallest indent in the range. Line comments are
+/// preferred; filetypes with only block comments get one per line.
+#[derive(Clone, Debug)]
+pub struct FiletypeCommenter {
+    filetypes: HashMap<String, CommentTokens>
+}
+
+impl FiletypeCommenter {
+    pub fn empty() -> FiletypeCommenter {
+        FiletypeCommenter { filetypes: HashMap::new() }
+    }
+
+    pub fn with_filetype(mut self, filetype: &str, tokens: CommentTokens) -> FiletypeCommenter {
+        self.filetypes.insert(filetype.to_string(), tokens);
+        self
+    }
+
+    pub fn tokens(&self, filetype: &str) -> Option<&CommentTokens> {
+        self.filetypes.get(filetype)
+    }
+
+    pub fn toggle_lines(&self, filetype: &str, lines: &[String]) -> Option<Vec<String>> {
+        let (start, end) = match self.tokens(filetype)? {
+            CommentTokens { line: Some(token), .. } => (token.as_str(), ""),
+            CommentTokens { block: Some((start, end)), .. } => (start.as_str(), end.as_str()),
+            _ => return None
+        };
+        let code = lines.iter().filter(|line| !line.trim().is_empty());
+        if code.clone().all(|line| is_commented(line, start, end)) {
+            Some(lines.iter().map(|line| uncomment(line, start, end)).collect())
+        } else {
+            // Only spaces and tabs count, so the indent is a char boundary
+            // on every line.
+            let indent = code.map(|line| line.len() - line.trim_start_matches([' ', '\t']).len()).min().unwrap_or(0);
+            Some(lines.iter().map(|line| comment(line, indent, start, end)).collect())
+        }
+    }
+}
+
+impl Default for FiletypeCommenter {
+    fn default() -> FiletypeCommenter {
+        let mut filetypes = HashMap::new();
+        for filetype in ["c", "cpp", "cs", "java", "javascript", "javascriptreact", "typescript",
+                         "typescriptreact", "go", "rust", "swift", "kotlin", "scala", "dart", "php"] {
+            filetypes.insert(filetype.to_string(), CommentTokens::line_and_block("//", "/*", "*/"));
+        }
+        for filetype in ["sh", "bash", "zsh", "fish", "python", "ruby", "perl", "yaml", "toml", "make",
+                         "cmake", "dockerfile", "conf", "r", "elixir", "nix", "gitcommit"] {
+            filetypes.insert(filetype.to_string(), CommentTokens::line("#"));
+        }
+        for filetype in ["lua", "sql", "haskell", "elm"] {
+            filetypes.insert(filetype.to_string(), CommentTokens::line("--"));
+        }
+        for filetype in ["lisp", "scheme", "clojure"] {
+            filetypes.insert(filetype.to_string(), CommentTokens::line(";;"));
+        }
+        for filetype in ["tex", "erlang", "matlab"] {
+            filetypes.insert(filetype.to_string(), CommentTokens::line("%"));
+        }
+        for filetype in ["html", "xml", "markdown", "vue", "svelte"] {
+            filetypes.insert(filetype.to_string(), CommentTokens::block("<!--", "-->"));
+        }
+        filetypes.insert("css".to_string(), CommentTokens::block("/*", "*/"));
+        filetypes.insert("scss".to_string(), CommentTokens::line_and_block("//", "/*", "*/"));
+        filetypes.insert("ocaml".to_string(), CommentTokens::block("(*", "*)"));
+        filetypes.insert("vim".to_string(), CommentTokens::line("\""));
+        FiletypeCommenter { filetypes }
+    }
+}
+
+impl CommentProvider for FiletypeCommenter {
+    fn toggle(&mut self, request: &CommentRequest) -> Option<Vec<String>> {
+        self.toggle_lines(request.filetype.as_deref()?, &request.lines)
+    }
+}
+
+fn is_commented(line: &str, start: &str, end: &str) -> bool {
+    let line = line.trim();
+    line.starts_with(start) && line.ends_with(end) && line.len() >= start.len() + end.len()
+}
+
+fn comment(line: &str, indent: usize, start: &str, end: &str) -> String {
+    if line.trim().is_empty() {
+        return line.to_string();
+    }
+    let (leading, code) = line.split_at(indent);
+    if end.is_empty() {
+        format!("{}{} {}", leading, start, code)
+    } else {
+        format!("{}{} {} {}", leading, start, code, end)
+    }
+}
+
+fn uncomment(line: &str, start: &str, end: &str) -> String {
+    if !is_commented(line, start, end) {
+        return line.to_string();
+    }
+    let indent = line.len() - line.trim_start().len();
+    let body = &line.trim_end()[indent + start.len()..];
+    let body = &body[..body.len() - end.len()];
+    let body = body.strip_prefix(' ').unwrap_or(body);
+    let body = if end.is_empty() { body } else { body.strip_suffix(' ').unwrap_or(body) };
+    format!("{}{}", &line[..indent], body)
+}
+
+thread_local! {
+    static PROVIDER: HandlerSlot<dyn CommentProvider> = const { HandlerSlot::new() };
+}
+
+pub fn vim_set_comment_provider<P: CommentProvider + 'static>(provider: P) {
+    PROVIDER.with(|slot| slot.set(Some(Box::new(provider))));
+    unsafe { vimSetToggleCommentsCallback(Some(toggle_comments)); }
+}
+
+pub fn vim_clear_comment_provider() {
+    PROVIDER.with(|slot| slot.set(None));
+    unsafe { vimSetToggleCommentsCallback(None); }
+}
+
+unsafe extern "C" fn toggle_comments(
+    buf: *mut buf_T,
+    start_line: linenr_T,
+    end_line: linenr_T,
+    out_count: *mut linenr_T,
+    out_lines: *mut *mut *mut char_u
+) -> c_int {
+    if buf.is_null() {
+        return 0;
+    }
+    let request = CommentRequest {
+        buffer_id: vimBufferGetId(buf),
+        filetype: vim_buffer_get_filetype(&mut *buf),
+        start_line,
+        end_line,
+        lines: (start_line..=end_line)
+            .map(|line| buffer_line(buf, line).unwrap_or_default())
+            .collect()
+    };
+    match PROVIDER.with(|slot| slot.call(|provider| provider.toggle(&request))) {
+        Some(Some(lines)) => {
+            *out_count = lines.len() as linenr_T;
+            *out_lines = lines_to_vim(&lines);
+            1
+        },
+        _ => 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn filetype_commenter_test() {
+        let commenter = FiletypeCommenter::default();
+        let code = lines(&["fn main() {", "    ship();", "", "}"]);
+        let commented = commenter.toggle_lines("rust", &code).expect("Comment rust");
+        assert_eq!(commented, lines(&["// fn main() {", "//     ship();", "", "// }"]));
+        assert_eq!(commenter.toggle_lines("rust", &commented), Some(code));
+
+        let mixed = lines(&["  # planet", "  express"]);
+        assert_eq!(commenter.toggle_lines("python", &mixed), Some(lines(&["  # # planet", "  # express"])));
+
+        let wide = lines(&["\u{3000}hermes", "  conrad"]);
+        assert_eq!(commenter.toggle_lines("python", &wide), Some(lines(&["# \u{3000}hermes", "#   conrad"])));
+
+        let html = lines(&["<p>Bite my shiny metal</p>"]);
+        let commented = commenter.toggle_lines("html", &html).expect("Comment html");
+        assert_eq!(commented, lines(&["<!-- <p>Bite my shiny metal</p> -->"]));
+        assert_eq!(commenter.toggle_lines("html", &commented), Some(html));
+
+        assert_eq!(commenter.toggle_lines("brainfuck", &commented), None);
+    }
+}
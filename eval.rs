@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::eval_string;
+
+// Values of type(), as in v:t_number and friends.
+const TYPE_FLOAT: i64 = 5;
+const TYPE_BLOB: i64 = 10;
+
+/// A Vim script value. Values travel through `json_encode`, so a Blob nested
+/// inside a List or Dict arrives as a List of Numbers.
+#[derive(Clone, PartialEq, Debug)]
+pub enum VimValue {
+    Number(i64),
+    Float(f64),
+    String(String),
+    List(Vec<VimValue>),
+    Dict(BTreeMap<String, VimValue>),
+    Bool(bool),
+    Null,
+    Blob(Vec<u8>)
+}
+
+impl VimValue {
+    pub fn as_number(&self) -> Option<i64> {
+        match self {
+            VimValue::Number(number) => Some(*number),
+            VimValue::Bool(value) => Some(*value as i64),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            VimValue::String(value) => Some(value),
+            _ => None
+        }
+    }
+
+    /// Vim's notion of truth: `v:true` or a non-zero Number.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            VimValue::Bool(value) => Some(*value),
+            VimValue::Number(number) => Some(*number != 0),
+            _ => None
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[VimValue]> {
+        match self {
+            VimValue::List(items) => Some(items),
+            _ => None
+        }
+    }
+
+    pub fn as_dict(&self) -> Option<&BTreeMap<String, VimValue>> {
+        match self {
+            VimValue::Dict(entries) => Some(entries),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EvalError {
+    pub expression: String
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Failed to evaluate {}", self.expression)
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// Evaluates a Vim script expression such as `&shiftwidth`,
+/// `expand('%:p')` or `g:config`. Fails if the expression is invalid or
+/// evaluates to something without a plain value, such as a Funcref or Job.
+pub fn vim_eval(expression: &str) -> Result<VimValue, EvalError> {
+    let error = || EvalError { expression: expression.to_string() };
+    // The expression is only evaluated once, even though both its type and
+    // value are needed.
+    let wrapped = format!("json_encode(map([({})], {{_, v -> [type(v), v]}})[0])", expression);
+    let json = eval_string(&wrapped).ok_or_else(error)?;
+    match decode_json(&json).ok_or_else(error)? {
+        VimValue::List(mut pair) if pair.len() == 2 => {
+            let value = pair.pop().unwrap();
+            match (pair[0].as_number(), value) {
+                (Some(TYPE_BLOB), VimValue::List(bytes)) => bytes.iter()
+                    .map(|byte| byte.as_number().and_then(|byte| u8::try_from(byte).ok()))
+                    .collect::<Option<Vec<u8>>>()
+                    .map(VimValue::Blob)
+                    .ok_or_else(error),
+                (Some(TYPE_FLOAT), VimValue::Number(number)) => Ok(VimValue::Float(number as f64)),
+                (Some(_), value) => Ok(value),
+                (None, _) => Err(error())
+            }
+        },
+        _ => Err(error())
+    }
+}
+
+fn decode_json(text: &str) -> Option<VimValue> {
+    let mut decoder = JsonDecoder { text, position: 0 };
+    let value = decoder.value()?;
+    decoder.skip_whitespace();
+    if decoder.position == text.len() { Some(value) } else { None }
+}
+
+// Just enough JSON for what json_encode() produces, which also includes NaN
+// and Infinity for special Floats.
+struct JsonDecoder<'a> {
+    text: &'a str,
+    position: usize
+}
+
+impl<'a> JsonDecoder<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self) -> Option<VimValue> {
+        self.skip_whitespace();
+        let literals = [
+            ("null", VimValue::Null),
+            ("true", VimValue::Bool(true)),
+            ("false", VimValue::Bool(false)),
+            ("NaN", VimValue::Float(f64::NAN)),
+            ("Infinity", VimValue::Float(f64::INFINITY)),
+            ("-Infinity", VimValue::Float(f64::NEG_INFINITY))
+        ];
+        for (literal, value) in literals {
+            if self.eat(literal) {
+                return Some(value);
+            }
+        }
+        match self.rest().chars().next()? {
+            '"' => self.string().map(VimValue::String),
+            '[' => {
+                self.position += 1;
+                let mut items = Vec::new();
+                if !self.eat("]") {
+                    loop {
+                        items.push(self.value()?);
+                        if self.eat("]") {
+                            break;
+                        }
+                        if !self.eat(",") {
+                            return None;
+                        }
+                    }
+                }
+                Some(VimValue::List(items))
+            },
+            '{' => {
+                self.position += 1;
+                let mut entries = BTreeMap::new();
+                if !self.eat("}") {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+                        if !self.eat(":") {
+                            return None;
+                        }
+                        entries.insert(key, self.value()?);
+                        if self.eat("}") {
+                            break;
+                        }
+                        if !self.eat(",") {
+                            return None;
+                        }
+                    }
+                }
+                Some(VimValue::Dict(entries))
+            },
+            _ => self.number()
+        }
+    }
+
+    fn number(&mut self) -> Option<VimValue> {
+        let rest = self.rest();
+        let length = rest.find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c))).unwrap_or(rest.len());
+        let number = &rest[..length];
+        self.position += length;
+        if number.contains(['.', 'e', 'E']) {
+            number.parse().ok().map(VimValue::Float)
+        } else {
+            number.parse().ok().map(VimValue::Number)
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let mut chars = self.rest().strip_prefix('"')?.char_indices();
+        let mut value = String::new();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += index + 2;
+                    return Some(value);
+                },
+                '\\' => match chars.next()?.1 {
+                    'b' => value.push('\x08'),
+                    'f' => value.push('\x0c'),
+                    'n' => value.push('\n'),
+                    'r' => value.push('\r'),
+                    't' => value.push('\t'),
+                    'u' => {
+                        let high = hex_unit(&mut chars)?;
+                        // Characters outside the BMP come as a surrogate pair.
+                        let code = if (0xd800..0xdc00).contains(&high) {
+                            if chars.next()?.1 != '\\' || chars.next()?.1 != 'u' {
+                                return None;
+                            }
+                            let low = hex_unit(&mut chars)?;
+                            0x10000 + ((high - 0xd800) << 10) + low.checked_sub(0xdc00)?
+                        } else {
+                            high
+                        };
+                        value.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    },
+                    c => value.push(c)
+                },
+                c => value.push(c)
+            }
+        }
+        None
+    }
+}
+
+fn hex_unit(chars: &mut std::str::CharIndices) -> Option<u32> {
+    let hex: String = chars.take(4).map(|(_, c)| c).collect();
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_json_test() {
+        assert_eq!(decode_json("4"), Some(VimValue::Number(4)));
+        assert_eq!(decode_json("-1.5e2"), Some(VimValue::Float(-150.0)));
+        assert_eq!(decode_json("\"caf\\u00e9 \\ud83d\\ude80\\n\""), Some(VimValue::String("caf\u{e9} \u{1f680}\n".to_string())));
+        assert_eq!(decode_json("-Infinity"), Some(VimValue::Float(f64::NEG_INFINITY)));
+
+        let mut dict = BTreeMap::new();
+        dict.insert("crew".to_string(), VimValue::List(vec![VimValue::String("fry".to_string()), VimValue::Null]));
+        dict.insert("ship".to_string(), VimValue::Bool(true));
+        assert_eq!(decode_json(" {\"crew\": [\"fry\", null], \"ship\": true} "), Some(VimValue::Dict(dict)));
+
+        assert_eq!(decode_json("[1, 2"), None);
+        assert_eq!(decode_json("[1] 2"), None);
+    }
+}
@@ -0,0 +1,147 @@
+use std::cell::{ Cell, RefCell };
+use libc::{ c_int, c_long };
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::{ autocmd, macros, mapping, option, shell, terminal, viewport, window, AutoCmd, MapMode, Mapping, OptionScope, OptionValue, ScrollDirection, SplitKind, TerminalRequest, WindowMove };
+
+/// Notifications libvim raises while processing input. Listeners registered
+/// with `vim_add_event_listener` receive them in the order they happened.
+#[derive(Clone, PartialEq, Debug)]
+pub enum VimEvent {
+    MacroRecordingStarted { register: char },
+    MacroRecordingStopped { register: char, keys: String },
+    /// libvim keeps a single window, so splits are left for the host to lay
+    /// out.
+    WindowSplit { kind: SplitKind, filename: Option<String> },
+    WindowMoved { movement: WindowMove, count: c_int },
+    /// Raised by scroll commands such as `zz`, `<C-d>` or `zh`.
+    Scroll { direction: ScrollDirection, count: c_long },
+    /// `:terminal` wants a job started; see `PtyTerminals` for a ready-made
+    /// implementation.
+    TerminalRequested(TerminalRequest),
+    /// Output of `:!cmd`, which libvim does not display itself.
+    ShellOutput { command: String, output: String, silent: bool },
+    /// A `:map` family command defined a mapping.
+    MappingAdded(Mapping),
+    /// An `:unmap` family command removed `lhs` from the modes in `mode`.
+    MappingRemoved { mode: MapMode, lhs: String },
+    /// An autocommand event fired; see `vim_on_autocmd` to subscribe to one.
+    AutoCommand(AutoCmd),
+    /// `:set` or one of its variants changed an option. Boolean options
+    /// report `OptionValue::Bool`.
+    OptionChanged { name: String, short_name: String, value: OptionValue, scope: OptionScope }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct EventListenerId(usize);
+
+type Listener = (EventListenerId, Box<dyn FnMut(&VimEvent)>);
+
+thread_local! {
+    static LISTENERS: RefCell<Vec<Listener>> = const { RefCell::new(Vec::new()) };
+    static REMOVED: RefCell<Vec<EventListenerId>> = const { RefCell::new(Vec::new()) };
+    static PENDING: RefCell<VecDeque<VimEvent>> = const { RefCell::new(VecDeque::new()) };
+    static DISPATCHING: Cell<bool> = const { Cell::new(false) };
+    static NEXT_ID: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn vim_add_event_listener<F: FnMut(&VimEvent) + 'static>(listener: F) -> EventListenerId {
+    let id = EventListenerId(NEXT_ID.with(|next| next.replace(next.get() + 1)));
+    LISTENERS.with(|listeners| listeners.borrow_mut().push((id, Box::new(listener))));
+    id
+}
+
+pub fn vim_remove_event_listener(id: EventListenerId) {
+    LISTENERS.with(|listeners| listeners.borrow_mut().retain(|(listener_id, _)| *listener_id != id));
+    if DISPATCHING.with(|dispatching| dispatching.get()) {
+        REMOVED.with(|removed| removed.borrow_mut().push(id));
+    }
+}
+
+// Keeps a host-owned model up to date, as the `attach` constructors do. The
+// listener only holds a weak reference, so dropping the model frees it, and
+// events that arrive while the host has the model borrowed are applied along
+// with the next one instead of panicking inside a libvim callback.
+pub(crate) fn follow_events<T: 'static>(
+    model: &Rc<RefCell<T>>,
+    mut handle: impl FnMut(&mut T, &VimEvent) + 'static
+) -> EventListenerId {
+    let model = Rc::downgrade(model);
+    let mut held: VecDeque<VimEvent> = VecDeque::new();
+    vim_add_event_listener(move |event| {
+        let shared = match model.upgrade() {
+            Some(shared) => shared,
+            None => return
+        };
+        held.push_back(event.clone());
+        if let Ok(mut model) = shared.try_borrow_mut() {
+            for event in held.drain(..) {
+                handle(&mut model, &event);
+            }
+        };
+    })
+}
+
+pub(crate) fn install_event_callbacks() {
+    macros::install_callbacks();
+    window::install_callbacks();
+    viewport::install_callbacks();
+    terminal::install_callbacks();
+    shell::install_callbacks();
+    mapping::install_callbacks();
+    autocmd::install_callbacks();
+    option::install_callbacks();
+}
+
+// Listeners are free to call back into libvim, which may raise more events.
+// Those are queued and delivered once the current event has been handled.
+pub(crate) fn emit(event: VimEvent) {
+    PENDING.with(|pending| pending.borrow_mut().push_back(event));
+    if DISPATCHING.with(|dispatching| dispatching.replace(true)) {
+        return;
+    }
+    while let Some(event) = PENDING.with(|pending| pending.borrow_mut().pop_front()) {
+        let mut listeners = LISTENERS.with(|listeners| listeners.take());
+        for (_, listener) in listeners.iter_mut() {
+            listener(&event);
+        }
+        LISTENERS.with(|current| {
+            let mut current = current.borrow_mut();
+            listeners.append(&mut current);
+            let removed = REMOVED.with(|removed| removed.take());
+            listeners.retain(|(id, _)| !removed.contains(id));
+            *current = listeners;
+        });
+    }
+    DISPATCHING.with(|dispatching| dispatching.set(false));
+}
+
+/// Holds the single host handler for a libvim request. The handler is taken
+/// out while it runs so it can call back into libvim, and is only put back if
+/// it was not replaced in the meantime.
+pub(crate) struct HandlerSlot<F: ?Sized> {
+    handler: RefCell<Option<Box<F>>>,
+    generation: Cell<usize>
+}
+
+impl<F: ?Sized> HandlerSlot<F> {
+    pub(crate) const fn new() -> HandlerSlot<F> {
+        HandlerSlot { handler: RefCell::new(None), generation: Cell::new(0) }
+    }
+
+    pub(crate) fn set(&self, handler: Option<Box<F>>) {
+        self.generation.set(self.generation.get() + 1);
+        *self.handler.borrow_mut() = handler;
+    }
+
+    pub(crate) fn call<R>(&self, call: impl FnOnce(&mut F) -> R) -> Option<R> {
+        let generation = self.generation.get();
+        let mut handler = self.handler.borrow_mut().take()?;
+        let result = call(&mut handler);
+        if self.generation.get() == generation {
+            *self.handler.borrow_mut() = Some(handler);
+        }
+        Some(result)
+    }
+}
@@ -0,0 +1,84 @@
+use libc::{ c_int, c_long };
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{
+    firstbuf, pos_T, vimBufferGetLastChangedTick, vimCursorGetPosition, vimVisualGetRange,
+    vimVisualGetType, vimVisualIsActive, vim_add_event_listener, vim_get_mode,
+    vim_remove_event_listener, vim_send_key, CursorPosition, Key, RegisterKind, VimEvent, VimMode
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Selection {
+    pub kind: RegisterKind,
+    pub start: CursorPosition,
+    pub end: CursorPosition
+}
+
+/// The state after `vim_feed` processed a batch of keys.
+#[derive(Debug)]
+pub struct InputResult {
+    pub mode: VimMode,
+    pub cursor: CursorPosition,
+    /// The visual selection, if visual mode is active.
+    pub selection: Option<Selection>,
+    /// Ids of the buffers whose contents changed, in buffer list order.
+    pub changed_buffers: Vec<c_int>,
+    pub events: Vec<VimEvent>
+}
+
+/// Sends `keys`, in Vim notation, one key at a time and reports what they
+/// did. Event listeners still see the events as they happen.
+pub fn vim_feed(keys: &str) -> InputResult {
+    let before = change_ticks();
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let recorded = events.clone();
+    let listener = vim_add_event_listener(move |event| recorded.borrow_mut().push(event.clone()));
+
+    for key in Key::parse_sequence(keys) {
+        vim_send_key(key);
+    }
+
+    vim_remove_event_listener(listener);
+    let changed_buffers = change_ticks().into_iter()
+        .filter(|buffer| !before.contains(buffer))
+        .map(|(id, _)| id)
+        .collect();
+    let events = events.take();
+    InputResult {
+        mode: vim_get_mode(),
+        cursor: unsafe { vimCursorGetPosition() },
+        selection: visual_selection(),
+        changed_buffers,
+        events
+    }
+}
+
+fn visual_selection() -> Option<Selection> {
+    unsafe {
+        if vimVisualIsActive() == 0 {
+            return None;
+        }
+        let mut start: pos_T = std::mem::zeroed();
+        let mut end: pos_T = std::mem::zeroed();
+        vimVisualGetRange(&mut start, &mut end);
+        let kind = match vimVisualGetType() as u8 {
+            b'V' => RegisterKind::Line,
+            0x16 => RegisterKind::Block,
+            _ => RegisterKind::Char
+        };
+        Some(Selection { kind, start, end })
+    }
+}
+
+fn change_ticks() -> Vec<(c_int, c_long)> {
+    let mut ticks = Vec::new();
+    unsafe {
+        let mut buffer = firstbuf;
+        while !buffer.is_null() {
+            ticks.push(((*buffer).b_fnum, vimBufferGetLastChangedTick(buffer)));
+            buffer = (*buffer).b_next;
+        }
+    }
+    ticks
+}
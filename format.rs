@@ -0,0 +1,104 @@
+use libc::{ c_int, c_long };
+
+use crate::event::HandlerSlot;
+use crate::{
+    buffer_line, formatRequestType_T_INDENTATION, formatRequest_T, string_from_vim, vimBufferGetId,
+    vimCursorGetPosition, vimCursorSetPosition, vimSetFormatCallback, vim_buffer_get_filetype,
+    vim_buffer_set_lines, CursorPosition
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FormatKind {
+    /// `=`
+    Indentation,
+    /// `gq` and `gw`
+    Formatting
+}
+
+#[derive(Clone, Debug)]
+pub struct FormatRequest {
+    pub kind: FormatKind,
+    pub buffer_id: c_int,
+    pub filetype: Option<String>,
+    pub start: CursorPosition,
+    pub end: CursorPosition,
+    /// The lines from `start.lnum` to `end.lnum`, inclusive.
+    pub lines: Vec<String>,
+    /// Set for `gw`, which leaves the cursor where it was.
+    pub return_cursor: bool,
+    /// The `formatprg` or `equalprg` the command would have used.
+    pub command: Option<String>
+}
+
+pub trait Formatter {
+    /// Returns the lines that replace `request.lines`, or `None` to leave
+    /// the buffer untouched.
+    fn format(&mut self, request: &FormatRequest) -> Option<Vec<String>>;
+}
+
+impl<F: FnMut(&FormatRequest) -> Option<Vec<String>>> Formatter for F {
+    fn format(&mut self, request: &FormatRequest) -> Option<Vec<String>> {
+        self(request)
+    }
+}
+
+thread_local! {
+    static FORMATTER: HandlerSlot<dyn Formatter> = const { HandlerSlot::new() };
+}
+
+pub fn vim_set_formatter<F: Formatter + 'static>(formatter: F) {
+    FORMATTER.with(|slot| slot.set(Some(Box::new(formatter))));
+    unsafe { vimSetFormatCallback(Some(format_request)); }
+}
+
+pub fn vim_clear_formatter() {
+    FORMATTER.with(|slot| slot.set(None));
+    unsafe { vimSetFormatCallback(None); }
+}
+
+unsafe extern "C" fn format_request(format_request: *mut formatRequest_T) {
+    let raw = &*format_request;
+    if raw.buf.is_null() {
+        return;
+    }
+    let buffer = &mut *raw.buf;
+    let lines = (raw.start.lnum..=raw.end.lnum)
+        .map(|line| buffer_line(raw.buf, line).unwrap_or_default())
+        .collect();
+    let request = FormatRequest {
+        kind: if raw.formatType == formatRequestType_T_INDENTATION {
+            FormatKind::Indentation
+        } else {
+            FormatKind::Formatting
+        },
+        buffer_id: vimBufferGetId(raw.buf),
+        filetype: vim_buffer_get_filetype(buffer),
+        start: raw.start,
+        end: raw.end,
+        lines,
+        return_cursor: raw.returnCursor != 0,
+        command: string_from_vim(raw.cmd).filter(|command| !command.is_empty())
+    };
+
+    let cursor = vimCursorGetPosition();
+    let replacement = match FORMATTER.with(|slot| slot.call(|formatter| formatter.format(&request))) {
+        Some(Some(replacement)) => replacement,
+        _ => return
+    };
+    // A single buffer update is a single undo step.
+    vim_buffer_set_lines(&mut *raw.buf, request.start.lnum - 1, request.end.lnum, &replacement);
+
+    // Like Vim, `=` leaves the cursor on the first non-blank of the first
+    // line and `gq` on the last line.
+    if request.return_cursor {
+        vimCursorSetPosition(cursor);
+    } else if request.kind == FormatKind::Indentation {
+        let indent = replacement.first()
+            .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+            .unwrap_or(0);
+        vimCursorSetPosition(CursorPosition { lnum: request.start.lnum, col: indent as c_int, coladd: 0 });
+    } else {
+        let last_line = request.start.lnum + (replacement.len() as c_long - 1).max(0);
+        vimCursorSetPosition(CursorPosition { lnum: last_line, col: 0, coladd: 0 });
+    }
+}
@@ -0,0 +1,68 @@
+use libc::c_int;
+
+use crate::event::HandlerSlot;
+use crate::{
+    gotoRequest_T, gotoTarget_T_DECLARATION, gotoTarget_T_DEFINITION, gotoTarget_T_HOVER,
+    gotoTarget_T_IMPLEMENTATION, gotoTarget_T_MESSAGES, gotoTarget_T_OUTLINE,
+    gotoTarget_T_TYPEDEFINITION, gotoTarget_T, vimSetGotoCallback, CursorPosition
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GotoTarget {
+    Definition,
+    Declaration,
+    Implementation,
+    TypeDefinition,
+    Hover,
+    Outline,
+    Messages
+}
+
+impl GotoTarget {
+    fn from_raw(raw: gotoTarget_T) -> Option<GotoTarget> {
+        let targets = [
+            (gotoTarget_T_DEFINITION, GotoTarget::Definition),
+            (gotoTarget_T_DECLARATION, GotoTarget::Declaration),
+            (gotoTarget_T_IMPLEMENTATION, GotoTarget::Implementation),
+            (gotoTarget_T_TYPEDEFINITION, GotoTarget::TypeDefinition),
+            (gotoTarget_T_HOVER, GotoTarget::Hover),
+            (gotoTarget_T_OUTLINE, GotoTarget::Outline),
+            (gotoTarget_T_MESSAGES, GotoTarget::Messages)
+        ];
+        targets.iter().find(|(raw_target, _)| *raw_target == raw).map(|(_, target)| *target)
+    }
+}
+
+/// Raised by `gd`, `gD`, `gi`, `gy`, `K`, `gO` and `g<`. `location` is the
+/// cursor position the command was issued from.
+#[derive(Clone, Copy, Debug)]
+pub struct GotoRequest {
+    pub target: GotoTarget,
+    pub location: CursorPosition,
+    pub count: c_int
+}
+
+thread_local! {
+    static HANDLER: HandlerSlot<dyn FnMut(&GotoRequest) -> bool> = const { HandlerSlot::new() };
+}
+
+/// The handler returns `true` when the host took care of the request;
+/// otherwise libvim falls back to its own behaviour (tags, `keywordprg`).
+pub fn vim_set_goto_handler<F: FnMut(&GotoRequest) -> bool + 'static>(handler: F) {
+    HANDLER.with(|slot| slot.set(Some(Box::new(handler))));
+    unsafe { vimSetGotoCallback(Some(goto_request)); }
+}
+
+pub fn vim_clear_goto_handler() {
+    HANDLER.with(|slot| slot.set(None));
+    unsafe { vimSetGotoCallback(None); }
+}
+
+unsafe extern "C" fn goto_request(goto_info: gotoRequest_T) -> c_int {
+    let target = match GotoTarget::from_raw(goto_info.target) {
+        Some(target) => target,
+        None => return 0
+    };
+    let request = GotoRequest { target, location: goto_info.location, count: goto_info.count };
+    HANDLER.with(|slot| slot.call(|handler| handler(&request))).unwrap_or(false) as c_int
+}
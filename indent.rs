@@ -0,0 +1,190 @@
+use libc::{ c_int, c_long };
+
+use crate::event::HandlerSlot;
+use crate::{
+    buf_T, buffer_line, char_u, get_indent_str, get_sw_value, string_from_vim, vimBufferGetId,
+    vimBufferGetLineCount, vimSetAutoIndentCallback, vim_buffer_get_filetype
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IndentAction {
+    /// Use the indent of the previous line.
+    Keep,
+    /// One `shiftwidth` more than the previous line.
+    Increase,
+    /// One `shiftwidth` less than the previous line.
+    Decrease,
+    /// An exact indent, in screen columns. libvim indents by whole
+    /// `shiftwidth`s relative to the previous line, so it is rounded to the
+    /// nearest one.
+    Absolute(c_int)
+}
+
+/// What an `IndentProvider` gets to look at when a new line is opened with
+/// `<CR>`, `o` or `O`.
+pub struct IndentContext {
+    pub line_number: c_long,
+    pub buffer_id: c_int,
+    pub filetype: Option<String>,
+    pub shiftwidth: c_long,
+    pub previous_line: String,
+    pub current_line: String,
+    buffer: *mut buf_T
+}
+
+impl IndentContext {
+    /// Reads any line of the buffer, for providers that need more context
+    /// than the surrounding two lines, such as tree-sitter based ones.
+    pub fn line(&self, line_number: c_long) -> Option<String> {
+        unsafe { buffer_line(self.buffer, line_number) }
+    }
+
+    pub fn line_count(&self) -> usize {
+        if self.buffer.is_null() {
+            return 0;
+        }
+        unsafe { vimBufferGetLineCount(self.buffer) }
+    }
+}
+
+pub trait IndentProvider {
+    fn indent(&mut self, context: &IndentContext) -> IndentAction;
+}
+
+impl<F: FnMut(&IndentContext) -> IndentAction> IndentProvider for F {
+    fn indent(&mut self, context: &IndentContext) -> IndentAction {
+        self(context)
+    }
+}
+
+/// Indents after a line that leaves a bracket open and dedents a line that
+/// starts by closing one.
+#[derive(Clone, Debug)]
+pub struct BracketIndent {
+    pairs: Vec<(char, char)>
+}
+
+impl BracketIndent {
+    pub fn new(pairs: &[(char, char)]) -> BracketIndent {
+        BracketIndent { pairs: pairs.to_vec() }
+    }
+
+    fn balance(&self, line: &str) -> i32 {
+        line.chars().fold(0, |balance, c| {
+            if self.pairs.iter().any(|(open, _)| *open == c) {
+                balance + 1
+            } else if self.pairs.iter().any(|(_, close)| *close == c) {
+                balance - 1
+            } else {
+                balance
+            }
+        })
+    }
+}
+
+impl Default for BracketIndent {
+    fn default() -> BracketIndent {
+        BracketIndent::new(&[('{', '}'), ('[', ']'), ('(', ')')])
+    }
+}
+
+impl IndentProvider for BracketIndent {
+    fn indent(&mut self, context: &IndentContext) -> IndentAction {
+        let opens = self.balance(&context.previous_line) > 0;
+        let closes = context.current_line.trim_start().chars().next()
+            .is_some_and(|c| self.pairs.iter().any(|(_, close)| *close == c));
+        match (opens, closes) {
+            (true, false) => IndentAction::Increase,
+            (false, true) => IndentAction::Decrease,
+            _ => IndentAction::Keep
+        }
+    }
+}
+
+thread_local! {
+    static PROVIDER: HandlerSlot<dyn IndentProvider> = const { HandlerSlot::new() };
+}
+
+pub fn vim_set_indent_provider<P: IndentProvider + 'static>(provider: P) {
+    PROVIDER.with(|slot| slot.set(Some(Box::new(provider))));
+    unsafe { vimSetAutoIndentCallback(Some(auto_indent)); }
+}
+
+pub fn vim_clear_indent_provider() {
+    PROVIDER.with(|slot| slot.set(None));
+    unsafe { vimSetAutoIndentCallback(None); }
+}
+
+// The number of shiftwidths from `previous` columns to `columns`, rounded to
+// the nearest.
+fn shiftwidths(columns: c_int, previous: c_int, shiftwidth: c_long) -> c_int {
+    let shiftwidth = shiftwidth.max(1) as c_int;
+    let delta = columns.max(0) - previous;
+    (delta + delta.signum() * shiftwidth / 2) / shiftwidth
+}
+
+unsafe extern "C" fn auto_indent(
+    lnum: c_int,
+    buf: *mut buf_T,
+    prev_line: *mut char_u,
+    current_line: *mut char_u
+) -> c_int {
+    let context = IndentContext {
+        line_number: lnum as c_long,
+        buffer_id: if buf.is_null() { 0 } else { vimBufferGetId(buf) },
+        filetype: if buf.is_null() { None } else { vim_buffer_get_filetype(&mut *buf) },
+        shiftwidth: if buf.is_null() { 0 } else { get_sw_value(buf) },
+        previous_line: string_from_vim(prev_line).unwrap_or_default(),
+        current_line: string_from_vim(current_line).unwrap_or_default(),
+        buffer: buf
+    };
+    match PROVIDER.with(|slot| slot.call(|provider| provider.indent(&context))) {
+        Some(IndentAction::Increase) => 1,
+        Some(IndentAction::Decrease) => -1,
+        Some(IndentAction::Absolute(columns)) => {
+            let previous = if prev_line.is_null() || buf.is_null() {
+                0
+            } else {
+                get_indent_str(prev_line, (*buf).b_p_ts as c_int, 0)
+            };
+            shiftwidths(columns, previous, context.shiftwidth)
+        },
+        Some(IndentAction::Keep) | None => 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    fn context(previous_line: &str, current_line: &str) -> IndentContext {
+        IndentContext {
+            line_number: 2,
+            buffer_id: 1,
+            filetype: None,
+            shiftwidth: 4,
+            previous_line: previous_line.to_string(),
+            current_line: current_line.to_string(),
+            buffer: ptr::null_mut()
+        }
+    }
+
+    #[test]
+    fn bracket_indent_test() {
+        let mut provider = BracketIndent::default();
+        assert_eq!(provider.indent(&context("fn main() {", "")), IndentAction::Increase);
+        assert_eq!(provider.indent(&context("    let x = 1;", "}")), IndentAction::Decrease);
+        assert_eq!(provider.indent(&context("fn main() {", "}")), IndentAction::Keep);
+        assert_eq!(provider.indent(&context("call(a, b)", "")), IndentAction::Keep);
+    }
+
+    #[test]
+    fn shiftwidths_test() {
+        assert_eq!(shiftwidths(8, 4, 4), 1);
+        assert_eq!(shiftwidths(0, 8, 4), -2);
+        assert_eq!(shiftwidths(5, 0, 4), 1);
+        assert_eq!(shiftwidths(7, 0, 4), 2);
+        assert_eq!(shiftwidths(-3, 4, 0), -4);
+    }
+}
@@ -0,0 +1,210 @@
+use libc::{ c_char, c_int };
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use crate::event::HandlerSlot;
+use crate::{
+    vimSetFunctionGetCharCallback, MOD_MASK_ALT, MOD_MASK_CMD, MOD_MASK_CTRL, MOD_MASK_META,
+    MOD_MASK_SHIFT
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub control: bool,
+    pub alt: bool,
+    pub meta: bool,
+    /// The Command key on macOS and the Super key elsewhere, `<D-…>` in Vim
+    /// notation.
+    pub command: bool
+}
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers { shift: false, control: false, alt: false, meta: false, command: false };
+
+    pub fn from_mask(mask: c_int) -> Modifiers {
+        let mask = mask as u32;
+        Modifiers {
+            shift: mask & MOD_MASK_SHIFT != 0,
+            control: mask & MOD_MASK_CTRL != 0,
+            alt: mask & MOD_MASK_ALT != 0,
+            meta: mask & MOD_MASK_META != 0,
+            command: mask & MOD_MASK_CMD != 0
+        }
+    }
+
+    pub fn to_mask(self) -> c_int {
+        let masks = [
+            (self.shift, MOD_MASK_SHIFT),
+            (self.control, MOD_MASK_CTRL),
+            (self.alt, MOD_MASK_ALT),
+            (self.meta, MOD_MASK_META),
+            (self.command, MOD_MASK_CMD)
+        ];
+        masks.iter().filter(|(set, _)| *set).fold(0, |mask, (_, bit)| mask | *bit) as c_int
+    }
+
+    pub fn is_empty(self) -> bool {
+        self == Modifiers::NONE
+    }
+}
+
+/// How `getchar()` was called.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GetCharMode {
+    /// `getchar()`: wait until a character is available.
+    Wait,
+    /// `getchar(0)`: only return a character that is already available.
+    NoWait,
+    /// `getchar(1)`: report the next character without consuming it.
+    Peek
+}
+
+impl GetCharMode {
+    fn from_raw(mode: c_int) -> GetCharMode {
+        match mode {
+            0 => GetCharMode::NoWait,
+            1 => GetCharMode::Peek,
+            _ => GetCharMode::Wait
+        }
+    }
+}
+
+/// Supplies the characters scripts read with `getchar()`. Returning `None`
+/// means no character is available, which for `GetCharMode::Wait` is how a
+/// source gives up after a timeout.
+pub trait InputSource {
+    fn get_char(&mut self, mode: GetCharMode) -> Option<(char, Modifiers)>;
+}
+
+impl<F: FnMut(GetCharMode) -> Option<(char, Modifiers)>> InputSource for F {
+    fn get_char(&mut self, mode: GetCharMode) -> Option<(char, Modifiers)> {
+        self(mode)
+    }
+}
+
+/// Hands out queued characters and never waits. Clones share the queue, so
+/// tests can keep one to push into after installing another.
+#[derive(Clone, Debug, Default)]
+pub struct QueueInput {
+    queue: Rc<RefCell<VecDeque<(char, Modifiers)>>>
+}
+
+impl QueueInput {
+    pub fn new() -> QueueInput {
+        QueueInput::default()
+    }
+
+    pub fn push(&self, character: char) {
+        self.push_with_modifiers(character, Modifiers::NONE);
+    }
+
+    pub fn push_str(&self, text: &str) {
+        text.chars().for_each(|character| self.push(character));
+    }
+
+    pub fn push_with_modifiers(&self, character: char, modifiers: Modifiers) {
+        self.queue.borrow_mut().push_back((character, modifiers));
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.borrow().is_empty()
+    }
+}
+
+impl InputSource for QueueInput {
+    fn get_char(&mut self, mode: GetCharMode) -> Option<(char, Modifiers)> {
+        let mut queue = self.queue.borrow_mut();
+        match mode {
+            GetCharMode::Peek => queue.front().copied(),
+            GetCharMode::Wait | GetCharMode::NoWait => queue.pop_front()
+        }
+    }
+}
+
+/// Reads characters sent from the host's input thread, blocking for at
+/// most `timeout` (or for as long as it takes without one).
+pub struct ChannelInput {
+    receiver: Receiver<(char, Modifiers)>,
+    timeout: Option<Duration>,
+    peeked: Option<(char, Modifiers)>
+}
+
+impl ChannelInput {
+    pub fn new(receiver: Receiver<(char, Modifiers)>, timeout: Option<Duration>) -> ChannelInput {
+        ChannelInput { receiver, timeout, peeked: None }
+    }
+}
+
+impl InputSource for ChannelInput {
+    fn get_char(&mut self, mode: GetCharMode) -> Option<(char, Modifiers)> {
+        let next = match self.peeked.take() {
+            Some(peeked) => Some(peeked),
+            None => match (mode, self.timeout) {
+                (GetCharMode::Wait, Some(timeout)) => self.receiver.recv_timeout(timeout).ok(),
+                (GetCharMode::Wait, None) => self.receiver.recv().ok(),
+                (GetCharMode::NoWait | GetCharMode::Peek, _) => self.receiver.try_recv().ok()
+            }
+        };
+        if mode == GetCharMode::Peek {
+            self.peeked = next;
+        }
+        next
+    }
+}
+
+thread_local! {
+    static SOURCE: HandlerSlot<dyn InputSource> = const { HandlerSlot::new() };
+}
+
+pub fn vim_set_input_source<S: InputSource + 'static>(source: S) {
+    SOURCE.with(|slot| slot.set(Some(Box::new(source))));
+    unsafe { vimSetFunctionGetCharCallback(Some(get_char)); }
+}
+
+pub fn vim_clear_input_source() {
+    SOURCE.with(|slot| slot.set(None));
+    unsafe { vimSetFunctionGetCharCallback(None); }
+}
+
+// libvim takes a single byte back, so only ASCII characters can be
+// delivered; anything else reads as no character.
+unsafe extern "C" fn get_char(mode: c_int, character: *mut c_char, mod_mask: *mut c_int) -> c_int {
+    let next = SOURCE.with(|slot| slot.call(|source| source.get_char(GetCharMode::from_raw(mode))));
+    match next {
+        Some(Some((next, modifiers))) if next.is_ascii() => {
+            *character = next as u8 as c_char;
+            *mod_mask = modifiers.to_mask();
+            1
+        },
+        _ => 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_input_test() {
+        let queue = QueueInput::new();
+        let mut source = queue.clone();
+        queue.push_str("y");
+        queue.push_with_modifiers('c', Modifiers { control: true, ..Modifiers::NONE });
+
+        assert_eq!(source.get_char(GetCharMode::Peek), Some(('y', Modifiers::NONE)));
+        assert_eq!(source.get_char(GetCharMode::Wait), Some(('y', Modifiers::NONE)));
+        let (_, modifiers) = source.get_char(GetCharMode::NoWait).expect("Read ctrl-c");
+        assert_eq!(Modifiers::from_mask(modifiers.to_mask()), modifiers);
+        assert_eq!(modifiers.to_mask(), MOD_MASK_CTRL as c_int);
+        assert_eq!(source.get_char(GetCharMode::Wait), None);
+        assert!(queue.is_empty());
+    }
+}
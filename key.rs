@@ -0,0 +1,318 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{ vim_input, vim_key, Modifiers };
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum KeyCode {
+    Char(char),
+    Enter,
+    Esc,
+    Tab,
+    Backspace,
+    Delete,
+    Insert,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Function(u8)
+}
+
+/// A single key press. Shift is implied by the character itself for
+/// `KeyCode::Char`, so it only shows in the notation of the other keys.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: Modifiers
+}
+
+impl Key {
+    pub fn new(code: KeyCode, modifiers: Modifiers) -> Key {
+        Key { code, modifiers }
+    }
+
+    pub fn char(character: char) -> Key {
+        Key::new(KeyCode::Char(character), Modifiers::NONE)
+    }
+
+    pub fn ctrl(character: char) -> Key {
+        Key::new(KeyCode::Char(character), Modifiers { control: true, ..Modifiers::NONE })
+    }
+
+    /// Parses keys in Vim notation, such as `gg` or `<C-w>j<Esc>`. Like in
+    /// Vim, a `<` that does not start a known key stands for itself.
+    pub fn parse_sequence(keys: &str) -> Vec<Key> {
+        let mut sequence = Vec::new();
+        let mut rest = keys;
+        while let Some(c) = rest.chars().next() {
+            let special = match closing_bracket(rest) {
+                Some(end) if c == '<' => rest[..=end].parse().ok().map(|key| (key, end + 1)),
+                _ => None
+            };
+            let (key, length) = special.unwrap_or((Key::char(c), c.len_utf8()));
+            sequence.push(key);
+            rest = &rest[length..];
+        }
+        sequence
+    }
+}
+
+// The `>` closing a `<...>` key that starts `keys`. It is searched for after
+// the modifiers and the first character of the name, so `<C->>` is found.
+fn closing_bracket(keys: &str) -> Option<usize> {
+    let bytes = keys.as_bytes();
+    let mut start = 1;
+    while bytes.len() > start + 2 && bytes[start + 1] == b'-' && b"SCMATD".contains(&bytes[start].to_ascii_uppercase()) {
+        start += 2;
+    }
+    let first = keys.get(start..)?.chars().next()?;
+    let name = start + first.len_utf8();
+    keys[name..].find('>').map(|end| name + end)
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseKeyError {
+    pub notation: String
+}
+
+impl fmt::Display for ParseKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown key {}", self.notation)
+    }
+}
+
+impl std::error::Error for ParseKeyError {}
+
+const NAMED_KEYS: [(&str, KeyCode); 17] = [
+    ("CR", KeyCode::Enter),
+    ("Esc", KeyCode::Esc),
+    ("Tab", KeyCode::Tab),
+    ("BS", KeyCode::Backspace),
+    ("Del", KeyCode::Delete),
+    ("Insert", KeyCode::Insert),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Space", KeyCode::Char(' ')),
+    ("lt", KeyCode::Char('<')),
+    ("Bslash", KeyCode::Char('\\'))
+];
+
+const ALIASES: [(&str, KeyCode); 6] = [
+    ("Enter", KeyCode::Enter),
+    ("Return", KeyCode::Enter),
+    ("Escape", KeyCode::Esc),
+    ("Backspace", KeyCode::Backspace),
+    ("Delete", KeyCode::Delete),
+    ("Bar", KeyCode::Char('|'))
+];
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let modifiers = self.modifiers;
+        let prefixes = [
+            (modifiers.shift && !matches!(self.code, KeyCode::Char(_)), "S-"),
+            (modifiers.control, "C-"),
+            (modifiers.alt, "M-"),
+            (modifiers.meta, "T-"),
+            (modifiers.command, "D-")
+        ];
+        let prefix: String = prefixes.iter().filter(|(set, _)| *set).map(|(_, prefix)| *prefix).collect();
+        let name = match self.code {
+            KeyCode::Char(c) if prefix.is_empty() && c != '<' => return write!(f, "{}", c),
+            KeyCode::Function(number) => format!("F{}", number),
+            KeyCode::Char(c) => NAMED_KEYS.iter()
+                .find(|(_, code)| *code == self.code)
+                .map(|(name, _)| name.to_string())
+                .unwrap_or_else(|| c.to_string()),
+            code => NAMED_KEYS.iter()
+                .find(|(_, named)| *named == code)
+                .map(|(name, _)| name.to_string())
+                .unwrap_or_default()
+        };
+        write!(f, "<{}{}>", prefix, name)
+    }
+}
+
+impl FromStr for Key {
+    type Err = ParseKeyError;
+
+    fn from_str(notation: &str) -> Result<Key, ParseKeyError> {
+        let error = || ParseKeyError { notation: notation.to_string() };
+        let mut chars = notation.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Ok(Key::char(c));
+        }
+        let mut name = notation.strip_prefix('<').and_then(|name| name.strip_suffix('>')).ok_or_else(error)?;
+
+        let mut modifiers = Modifiers::NONE;
+        while name.len() > 2 && name.as_bytes()[1] == b'-' {
+            match name.as_bytes()[0].to_ascii_uppercase() {
+                b'S' => modifiers.shift = true,
+                b'C' => modifiers.control = true,
+                b'M' | b'A' => modifiers.alt = true,
+                b'T' => modifiers.meta = true,
+                b'D' => modifiers.command = true,
+                _ => return Err(error())
+            }
+            name = &name[2..];
+        }
+
+        // Like in Vim, `<x>` is not a key without a modifier.
+        let mut chars = name.chars();
+        let code = if let (Some(c), None) = (chars.next(), chars.next()) {
+            if modifiers.is_empty() {
+                return Err(error());
+            }
+            KeyCode::Char(c)
+        } else if let Some(number) = name.strip_prefix(['F', 'f']).and_then(|number| number.parse().ok()) {
+            KeyCode::Function(number)
+        } else {
+            NAMED_KEYS.iter().chain(ALIASES.iter())
+                .find(|(known, _)| known.eq_ignore_ascii_case(name))
+                .map(|(_, code)| *code)
+                .ok_or_else(error)?
+        };
+        Ok(Key { code, modifiers })
+    }
+}
+
+/// Sends a key to libvim, as if it had been typed.
+pub fn vim_send_key(key: Key) {
+    match key.code {
+        KeyCode::Char(c) if key.modifiers.is_empty() => vim_input(&c.to_string()),
+        _ => vim_key(&key.to_string())
+    }
+}
+
+#[cfg(any(feature = "crossterm", feature = "termion"))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct UnsupportedKey;
+
+#[cfg(feature = "crossterm")]
+impl TryFrom<crossterm::event::KeyEvent> for Key {
+    type Error = UnsupportedKey;
+
+    fn try_from(event: crossterm::event::KeyEvent) -> Result<Key, UnsupportedKey> {
+        use crossterm::event::{ KeyCode as Code, KeyEventKind, KeyModifiers };
+
+        if event.kind == KeyEventKind::Release {
+            return Err(UnsupportedKey);
+        }
+        let mut modifiers = Modifiers {
+            shift: event.modifiers.contains(KeyModifiers::SHIFT),
+            control: event.modifiers.contains(KeyModifiers::CONTROL),
+            alt: event.modifiers.contains(KeyModifiers::ALT),
+            meta: event.modifiers.contains(KeyModifiers::META),
+            command: event.modifiers.contains(KeyModifiers::SUPER)
+        };
+        let code = match event.code {
+            Code::Char(c) => {
+                modifiers.shift = false;
+                KeyCode::Char(c)
+            },
+            Code::Enter => KeyCode::Enter,
+            Code::Esc => KeyCode::Esc,
+            Code::Tab => KeyCode::Tab,
+            Code::BackTab => {
+                modifiers.shift = true;
+                KeyCode::Tab
+            },
+            Code::Backspace => KeyCode::Backspace,
+            Code::Delete => KeyCode::Delete,
+            Code::Insert => KeyCode::Insert,
+            Code::Up => KeyCode::Up,
+            Code::Down => KeyCode::Down,
+            Code::Left => KeyCode::Left,
+            Code::Right => KeyCode::Right,
+            Code::Home => KeyCode::Home,
+            Code::End => KeyCode::End,
+            Code::PageUp => KeyCode::PageUp,
+            Code::PageDown => KeyCode::PageDown,
+            Code::F(number) => KeyCode::Function(number),
+            _ => return Err(UnsupportedKey)
+        };
+        Ok(Key { code, modifiers })
+    }
+}
+
+#[cfg(feature = "termion")]
+impl TryFrom<termion::event::Key> for Key {
+    type Error = UnsupportedKey;
+
+    fn try_from(key: termion::event::Key) -> Result<Key, UnsupportedKey> {
+        use termion::event::Key as TermionKey;
+
+        let control = Modifiers { control: true, ..Modifiers::NONE };
+        let alt = Modifiers { alt: true, ..Modifiers::NONE };
+        let (code, modifiers) = match key {
+            TermionKey::Char('\n') => (KeyCode::Enter, Modifiers::NONE),
+            TermionKey::Char('\t') => (KeyCode::Tab, Modifiers::NONE),
+            TermionKey::Char(c) => (KeyCode::Char(c), Modifiers::NONE),
+            TermionKey::Ctrl(c) => (KeyCode::Char(c), control),
+            TermionKey::Alt(c) => (KeyCode::Char(c), alt),
+            TermionKey::BackTab => (KeyCode::Tab, Modifiers { shift: true, ..Modifiers::NONE }),
+            TermionKey::Esc => (KeyCode::Esc, Modifiers::NONE),
+            TermionKey::Backspace => (KeyCode::Backspace, Modifiers::NONE),
+            TermionKey::Delete => (KeyCode::Delete, Modifiers::NONE),
+            TermionKey::Insert => (KeyCode::Insert, Modifiers::NONE),
+            TermionKey::Up => (KeyCode::Up, Modifiers::NONE),
+            TermionKey::Down => (KeyCode::Down, Modifiers::NONE),
+            TermionKey::Left => (KeyCode::Left, Modifiers::NONE),
+            TermionKey::Right => (KeyCode::Right, Modifiers::NONE),
+            TermionKey::Home => (KeyCode::Home, Modifiers::NONE),
+            TermionKey::End => (KeyCode::End, Modifiers::NONE),
+            TermionKey::PageUp => (KeyCode::PageUp, Modifiers::NONE),
+            TermionKey::PageDown => (KeyCode::PageDown, Modifiers::NONE),
+            TermionKey::F(number) => (KeyCode::Function(number), Modifiers::NONE),
+            _ => return Err(UnsupportedKey)
+        };
+        Ok(Key { code, modifiers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_notation_test() {
+        assert_eq!(Key::char('x').to_string(), "x");
+        assert_eq!(Key::ctrl('w').to_string(), "<C-w>");
+        assert_eq!(Key::char('<').to_string(), "<lt>");
+        let shift_tab = Key::new(KeyCode::Tab, Modifiers { shift: true, ..Modifiers::NONE });
+        assert_eq!(shift_tab.to_string(), "<S-Tab>");
+        assert_eq!(Key::new(KeyCode::Function(12), Modifiers::NONE).to_string(), "<F12>");
+
+        assert_eq!("<esc>".parse(), Ok(Key::new(KeyCode::Esc, Modifiers::NONE)));
+        assert_eq!("<s-TAB>".parse(), Ok(shift_tab));
+        assert_eq!("<C-->".parse(), Ok(Key::ctrl('-')));
+        assert!("<Hypnotoad>".parse::<Key>().is_err());
+
+        assert!("<x>".parse::<Key>().is_err());
+        assert_eq!("<C->>".parse(), Ok(Key::ctrl('>')));
+
+        let keys = Key::parse_sequence("g<C-w>j<<lt><x>");
+        assert_eq!(keys, vec![
+            Key::char('g'), Key::ctrl('w'), Key::char('j'), Key::char('<'), Key::char('<'),
+            Key::char('<'), Key::char('x'), Key::char('>')
+        ]);
+        assert_eq!(Key::parse_sequence("<C->>a"), vec![Key::ctrl('>'), Key::char('a')]);
+        let notation: String = Key::parse_sequence("<p>hi</p>").iter().map(Key::to_string).collect();
+        assert_eq!(notation, "<lt>p>hi<lt>/p>");
+        let keys = Key::parse_sequence("<Hypnotoad>");
+        assert_eq!(keys.len(), "<Hypnotoad>".len());
+        let notation: String = Key::parse_sequence("ia<lt><Esc>").iter().map(Key::to_string).collect();
+        assert_eq!(notation, "ia<lt><Esc>");
+    }
+}
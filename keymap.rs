@@ -0,0 +1,110 @@
+use libc::c_int;
+use std::cell::{ Cell, RefCell };
+
+use crate::mapping::canonical_keys;
+use crate::{
+    vim_eval, vim_execute, vim_register_command, vim_unregister_command, MapMode, UserCommand
+};
+
+/// What a mapping made with `vim_map` was triggered with.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MappingContext {
+    /// The single mode the keys were typed in. For visual mode the selection
+    /// has ended by the time the action runs, and is available through the
+    /// `'<` and `'>` marks.
+    pub mode: MapMode,
+    /// The count typed before the keys, 0 if there was none.
+    pub count: c_int,
+    pub register: char
+}
+
+struct NativeMapping {
+    command: String,
+    mode: MapMode,
+    keys: String
+}
+
+thread_local! {
+    static NATIVE_MAPPINGS: RefCell<Vec<NativeMapping>> = const { RefCell::new(Vec::new()) };
+    static NEXT_ID: Cell<usize> = const { Cell::new(0) };
+}
+
+// The map command for each supported mode, and how its right-hand side gets
+// to the command line.
+const MAP_COMMANDS: [(MapMode, &str, &str); 4] = [
+    (MapMode::NORMAL, "n", ":<C-u>"),
+    (MapMode::VISUAL, "x", ":<C-u>"),
+    (MapMode::OPERATOR_PENDING, "o", ":<C-u>"),
+    (MapMode::INSERT, "i", "<C-o>:")
+];
+
+/// Maps `lhs` in `mode` to a Rust closure. The keys go through Vim's own
+/// mapping engine, so `timeout`, counts and operators work as they do for
+/// `:map`; in operator-pending mode the action moves the cursor to define
+/// the motion. Returns false, mapping nothing, if `mode` includes anything
+/// besides normal, visual, operator-pending and insert mode.
+pub fn vim_map<F: FnMut(&MappingContext) + 'static>(mode: MapMode, lhs: &str, mut action: F) -> bool {
+    let supported = MAP_COMMANDS.iter().fold(MapMode::NONE, |supported, (mode, _, _)| supported | *mode);
+    if mode.is_empty() || !mode.without(supported).is_empty() {
+        return false;
+    }
+    let command = format!("LibvimRsMapping{}", NEXT_ID.with(|next| next.replace(next.get() + 1)));
+    vim_register_command(UserCommand::new(&command, move |args| {
+        let mode = MAP_COMMANDS.iter()
+            .find(|(_, prefix, _)| *prefix == args.args)
+            .map(|(mode, _, _)| *mode)
+            .unwrap_or(MapMode::NORMAL);
+        let count = vim_eval("v:count").ok().and_then(|count| count.as_number()).unwrap_or(0);
+        let register = vim_eval("v:register").ok()
+            .and_then(|register| register.as_str().and_then(|register| register.chars().next()))
+            .unwrap_or('"');
+        action(&MappingContext { mode, count: count as c_int, register });
+        Ok(())
+    }).internal());
+
+    let lhs = escape_lhs(lhs);
+    let keys = canonical_keys(&lhs);
+    forget(mode, &keys);
+    for (_, prefix, enter) in MAP_COMMANDS.iter().filter(|(map_mode, _, _)| mode.contains(*map_mode)) {
+        vim_execute(&format!("{}noremap <silent> {} {}{} {}<CR>", prefix, lhs, enter, command, prefix));
+    }
+    NATIVE_MAPPINGS.with(|mappings| mappings.borrow_mut().push(NativeMapping { command, mode, keys }));
+    true
+}
+
+/// Removes mappings made with `vim_map` for `lhs` in `mode`.
+pub fn vim_unmap(mode: MapMode, lhs: &str) {
+    let lhs = escape_lhs(lhs);
+    let keys = canonical_keys(&lhs);
+    let mapped = NATIVE_MAPPINGS.with(|mappings| mappings.borrow().iter()
+        .filter(|mapping| mapping.keys == keys)
+        .fold(MapMode::NONE, |mapped, mapping| mapped | mapping.mode));
+    for (map_mode, prefix, _) in MAP_COMMANDS.iter() {
+        if mode.contains(*map_mode) && mapped.contains(*map_mode) {
+            vim_execute(&format!("silent! {}unmap {}", prefix, lhs));
+        }
+    }
+    forget(mode, &keys);
+}
+
+// Drops `mode` from the native mappings of `keys`, along with the commands
+// of those no longer mapped in any mode.
+fn forget(mode: MapMode, keys: &str) {
+    let unused: Vec<String> = NATIVE_MAPPINGS.with(|mappings| {
+        let mut mappings = mappings.borrow_mut();
+        for mapping in mappings.iter_mut().filter(|mapping| mapping.keys == keys) {
+            mapping.mode = mapping.mode.without(mode);
+        }
+        let (unused, used) = mappings.drain(..).partition(|mapping| mapping.mode.is_empty());
+        *mappings = used;
+        unused.into_iter().map(|mapping: NativeMapping| mapping.command).collect()
+    });
+    for command in unused {
+        vim_unregister_command(&command);
+    }
+}
+
+// The left-hand side ends at the first space, and a bar ends the command.
+fn escape_lhs(lhs: &str) -> String {
+    lhs.replace(' ', "<Space>").replace('|', "<Bar>")
+}
@@ -0,0 +1,466 @@
+use libc::c_int;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::event::{ follow_events, vim_remove_event_listener, EventListenerId, VimEvent };
+use crate::{
+    vimBufferGetById, vimBufferGetCurrent, vimBufferGetId, vimBufferNew, vimBufferSetCurrent,
+    vimCursorGetPosition, vimCursorSetPosition, vimWindowGetLeftColumn, vimWindowGetTopLine,
+    vimWindowSetTopLeft, vim_buffer_open, CursorPosition, SplitKind, WindowMove
+};
+
+pub type ViewId = usize;
+
+/// What a view remembers while another one has libvim's single window.
+#[derive(Clone, Copy, Debug)]
+pub struct View {
+    pub id: ViewId,
+    pub buffer_id: c_int,
+    pub cursor: CursorPosition,
+    pub top_line: c_int,
+    pub left_column: c_int
+}
+
+/// Named after the Vim command that creates it: a `Horizontal` split stacks
+/// its children top to bottom, a `Vertical` one places them side by side.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SplitDirection {
+    Horizontal,
+    Vertical
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LayoutNode {
+    View(ViewId),
+    Split(SplitDirection, Vec<LayoutNode>)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64
+}
+
+/// A tree of views sharing libvim's single window. Split and window movement
+/// events reshape the tree, and moving the focus swaps the buffer, cursor and
+/// scroll position libvim works with.
+#[derive(Debug)]
+pub struct Layout {
+    root: LayoutNode,
+    views: HashMap<ViewId, View>,
+    focused: ViewId,
+    previous: Option<ViewId>,
+    next_id: ViewId,
+    listener: Option<EventListenerId>
+}
+
+impl Layout {
+    /// Starts with a single view showing libvim's current buffer.
+    pub fn new() -> Layout {
+        let view = View { id: 0, ..current_view_state() };
+        Layout {
+            root: LayoutNode::View(view.id),
+            views: HashMap::from([(view.id, view)]),
+            focused: view.id,
+            previous: None,
+            next_id: 1,
+            listener: None
+        }
+    }
+
+    /// Creates a layout that follows libvim's split and movement events until
+    /// it is dropped or detached.
+    pub fn attach() -> Rc<RefCell<Layout>> {
+        let layout = Rc::new(RefCell::new(Layout::new()));
+        let listener = follow_events(&layout, Layout::handle_event);
+        layout.borrow_mut().listener = Some(listener);
+        layout
+    }
+
+    /// Stops following libvim's events.
+    pub fn detach(&mut self) {
+        if let Some(listener) = self.listener.take() {
+            vim_remove_event_listener(listener);
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &VimEvent) {
+        match event {
+            VimEvent::WindowSplit { kind, filename } => self.split(*kind, filename.as_deref()),
+            VimEvent::WindowMoved { movement, count } => self.move_window(*movement, *count),
+            _ => {}
+        }
+    }
+
+    pub fn root(&self) -> &LayoutNode {
+        &self.root
+    }
+
+    pub fn focused(&self) -> ViewId {
+        self.focused
+    }
+
+    /// The focused view reflects libvim's live state rather than the last
+    /// saved one.
+    pub fn view(&self, id: ViewId) -> Option<View> {
+        if id == self.focused {
+            Some(View { id, ..current_view_state() })
+        } else {
+            self.views.get(&id).copied()
+        }
+    }
+
+    pub fn views(&self) -> Vec<ViewId> {
+        let mut ids = Vec::new();
+        collect_views(&self.root, &mut ids);
+        ids
+    }
+
+    /// Divides `width` by `height` cells evenly between the views.
+    pub fn rects(&self, width: f64, height: f64) -> Vec<(ViewId, Rect)> {
+        let mut rects = Vec::new();
+        layout_rects(&self.root, Rect { x: 0.0, y: 0.0, width, height }, &mut rects);
+        rects
+    }
+
+    /// Tab splits are left to the tab model.
+    pub fn split(&mut self, kind: SplitKind, filename: Option<&str>) {
+        let direction = match kind {
+            SplitKind::Horizontal | SplitKind::HorizontalNew => SplitDirection::Horizontal,
+            SplitKind::Vertical | SplitKind::VerticalNew => SplitDirection::Vertical,
+            SplitKind::Tab | SplitKind::TabNew => return
+        };
+        let current = View { id: self.focused, ..current_view_state() };
+        self.views.insert(current.id, current);
+
+        let new_buffer = match (kind, filename) {
+            (_, Some(filename)) => vim_buffer_open(filename).map(|buffer| buffer as *mut _),
+            (SplitKind::HorizontalNew | SplitKind::VerticalNew, None) => {
+                let buffer = unsafe { vimBufferNew(0) };
+                if buffer.is_null() { None } else { Some(buffer) }
+            },
+            _ => None
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        let view = match new_buffer {
+            Some(buffer) => View {
+                id,
+                buffer_id: unsafe { vimBufferGetId(buffer) },
+                cursor: CursorPosition { lnum: 1, col: 0, coladd: 0 },
+                top_line: 1,
+                left_column: 0
+            },
+            None => View { id, ..current }
+        };
+        self.views.insert(id, view);
+        insert_before(&mut self.root, self.focused, id, direction);
+        self.activate(id);
+    }
+
+    pub fn move_window(&mut self, movement: WindowMove, count: c_int) {
+        let count = count.max(1);
+        match movement {
+            WindowMove::CursorLeft | WindowMove::CursorRight | WindowMove::CursorUp | WindowMove::CursorDown => {
+                let mut target = self.focused;
+                for _ in 0..count {
+                    match self.neighbour(target, movement) {
+                        Some(neighbour) => target = neighbour,
+                        None => break
+                    }
+                }
+                self.focus(target);
+            },
+            WindowMove::CursorTopLeft => {
+                if let Some(first) = self.views().first() {
+                    self.focus(*first);
+                }
+            },
+            WindowMove::CursorBottomRight => {
+                if let Some(last) = self.views().last() {
+                    self.focus(*last);
+                }
+            },
+            WindowMove::CursorPrevious => {
+                if let Some(previous) = self.previous {
+                    self.focus(previous);
+                }
+            },
+            WindowMove::MoveFullLeft => self.move_to_edge(SplitDirection::Vertical, true),
+            WindowMove::MoveFullRight => self.move_to_edge(SplitDirection::Vertical, false),
+            WindowMove::MoveFullUp => self.move_to_edge(SplitDirection::Horizontal, true),
+            WindowMove::MoveFullDown => self.move_to_edge(SplitDirection::Horizontal, false),
+            WindowMove::RotateDownwards => {
+                for _ in 0..count {
+                    rotate(&mut self.root, self.focused, true);
+                }
+            },
+            WindowMove::RotateUpwards => {
+                for _ in 0..count {
+                    rotate(&mut self.root, self.focused, false);
+                }
+            }
+        }
+    }
+
+    /// Saves libvim's state into the focused view and loads `id`'s buffer,
+    /// cursor and scroll position into libvim.
+    pub fn focus(&mut self, id: ViewId) {
+        if id == self.focused {
+            return;
+        }
+        if !self.views.contains_key(&id) {
+            return;
+        }
+        self.save_state();
+        self.activate(id);
+    }
+
+    /// Stores libvim's state in the focused view, before handing libvim's
+    /// window to something else, like another tab page.
+    pub fn save_state(&mut self) {
+        self.views.insert(self.focused, View { id: self.focused, ..current_view_state() });
+    }
+
+    /// Loads the focused view back into libvim.
+    pub fn restore_state(&mut self) {
+        self.activate(self.focused);
+    }
+
+    // Opening a buffer for a new split already changed libvim's state, so the
+    // split saves the focused view itself and only activates the new one.
+    fn activate(&mut self, id: ViewId) {
+        let view = match self.views.get(&id) {
+            Some(view) => *view,
+            None => return
+        };
+        unsafe {
+            let buffer = vimBufferGetById(view.buffer_id);
+            if !buffer.is_null() {
+                vimBufferSetCurrent(buffer);
+            }
+            vimCursorSetPosition(view.cursor);
+            vimWindowSetTopLeft(view.top_line, view.left_column);
+        }
+        if id != self.focused {
+            self.previous = Some(self.focused);
+            self.focused = id;
+        }
+    }
+
+    /// Removes a view, e.g. after `:close`, focusing the previous view when
+    /// it was the focused one. The last view cannot be closed.
+    pub fn close(&mut self, id: ViewId) {
+        if self.views.len() <= 1 || !self.views.contains_key(&id) {
+            return;
+        }
+        if id == self.focused {
+            let next = self.previous
+                .filter(|previous| *previous != id && self.views.contains_key(previous))
+                .or_else(|| self.views().into_iter().find(|view| *view != id));
+            if let Some(next) = next {
+                self.focus(next);
+            }
+        }
+        if let Some(root) = remove_view(self.root.clone(), id) {
+            self.root = root;
+        }
+        self.views.remove(&id);
+        if self.previous == Some(id) {
+            self.previous = None;
+        }
+    }
+
+    fn neighbour(&self, id: ViewId, movement: WindowMove) -> Option<ViewId> {
+        const EPSILON: f64 = 1e-9;
+        let rects = self.rects(1.0, 1.0);
+        let (_, from) = *rects.iter().find(|(view, _)| *view == id)?;
+        rects.iter()
+            .filter(|(view, _)| *view != id)
+            .filter_map(|(view, rect)| {
+                let (touches, overlap) = match movement {
+                    WindowMove::CursorLeft => ((rect.x + rect.width - from.x).abs() < EPSILON, overlap(from.y, from.height, rect.y, rect.height)),
+                    WindowMove::CursorRight => ((from.x + from.width - rect.x).abs() < EPSILON, overlap(from.y, from.height, rect.y, rect.height)),
+                    WindowMove::CursorUp => ((rect.y + rect.height - from.y).abs() < EPSILON, overlap(from.x, from.width, rect.x, rect.width)),
+                    _ => ((from.y + from.height - rect.y).abs() < EPSILON, overlap(from.x, from.width, rect.x, rect.width))
+                };
+                if touches && overlap > EPSILON { Some((*view, overlap)) } else { None }
+            })
+            .fold(None, |best: Option<(ViewId, f64)>, candidate| match best {
+                Some(best) if best.1 >= candidate.1 => Some(best),
+                _ => Some(candidate)
+            })
+            .map(|(view, _)| view)
+    }
+
+    fn move_to_edge(&mut self, direction: SplitDirection, first: bool) {
+        if self.views.len() <= 1 {
+            return;
+        }
+        let rest = match remove_view(self.root.clone(), self.focused) {
+            Some(rest) => rest,
+            None => return
+        };
+        let mut children = match rest {
+            LayoutNode::Split(rest_direction, children) if rest_direction == direction => children,
+            rest => vec![rest]
+        };
+        if first {
+            children.insert(0, LayoutNode::View(self.focused));
+        } else {
+            children.push(LayoutNode::View(self.focused));
+        }
+        self.root = LayoutNode::Split(direction, children);
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout::new()
+    }
+}
+
+impl Drop for Layout {
+    fn drop(&mut self) {
+        self.detach();
+    }
+}
+
+fn current_view_state() -> View {
+    unsafe {
+        View {
+            id: 0,
+            buffer_id: vimBufferGetId(vimBufferGetCurrent()),
+            cursor: vimCursorGetPosition(),
+            top_line: vimWindowGetTopLine(),
+            left_column: vimWindowGetLeftColumn()
+        }
+    }
+}
+
+fn overlap(start: f64, length: f64, other_start: f64, other_length: f64) -> f64 {
+    (start + length).min(other_start + other_length) - start.max(other_start)
+}
+
+fn collect_views(node: &LayoutNode, ids: &mut Vec<ViewId>) {
+    match node {
+        LayoutNode::View(id) => ids.push(*id),
+        LayoutNode::Split(_, children) => children.iter().for_each(|child| collect_views(child, ids))
+    }
+}
+
+fn layout_rects(node: &LayoutNode, rect: Rect, rects: &mut Vec<(ViewId, Rect)>) {
+    match node {
+        LayoutNode::View(id) => rects.push((*id, rect)),
+        LayoutNode::Split(direction, children) => {
+            let share = 1.0 / children.len() as f64;
+            for (index, child) in children.iter().enumerate() {
+                let offset = index as f64 * share;
+                let child_rect = match direction {
+                    SplitDirection::Horizontal => Rect {
+                        y: rect.y + rect.height * offset,
+                        height: rect.height * share,
+                        ..rect
+                    },
+                    SplitDirection::Vertical => Rect {
+                        x: rect.x + rect.width * offset,
+                        width: rect.width * share,
+                        ..rect
+                    }
+                };
+                layout_rects(child, child_rect, rects);
+            }
+        }
+    }
+}
+
+// Vim opens the new window above or to the left of the current one.
+fn insert_before(node: &mut LayoutNode, target: ViewId, id: ViewId, direction: SplitDirection) -> bool {
+    match node {
+        LayoutNode::View(view) if *view == target => {
+            *node = LayoutNode::Split(direction, vec![LayoutNode::View(id), LayoutNode::View(target)]);
+            true
+        },
+        LayoutNode::View(_) => false,
+        LayoutNode::Split(split_direction, children) => {
+            if *split_direction == direction {
+                if let Some(index) = children.iter().position(|child| *child == LayoutNode::View(target)) {
+                    children.insert(index, LayoutNode::View(id));
+                    return true;
+                }
+            }
+            children.iter_mut().any(|child| insert_before(child, target, id, direction))
+        }
+    }
+}
+
+fn remove_view(node: LayoutNode, id: ViewId) -> Option<LayoutNode> {
+    match node {
+        LayoutNode::View(view) if view == id => None,
+        LayoutNode::View(view) => Some(LayoutNode::View(view)),
+        LayoutNode::Split(direction, children) => {
+            let mut remaining = Vec::new();
+            for child in children.into_iter().filter_map(|child| remove_view(child, id)) {
+                match child {
+                    LayoutNode::Split(child_direction, grandchildren) if child_direction == direction => {
+                        remaining.extend(grandchildren);
+                    },
+                    child => remaining.push(child)
+                }
+            }
+            match remaining.len() {
+                0 => None,
+                1 => remaining.pop(),
+                _ => Some(LayoutNode::Split(direction, remaining))
+            }
+        }
+    }
+}
+
+fn rotate(node: &mut LayoutNode, target: ViewId, downwards: bool) -> bool {
+    match node {
+        LayoutNode::View(_) => false,
+        LayoutNode::Split(_, children) => {
+            if children.contains(&LayoutNode::View(target)) {
+                if downwards {
+                    children.rotate_right(1);
+                } else {
+                    children.rotate_left(1);
+                }
+                true
+            } else {
+                children.iter_mut().any(|child| rotate(child, target, downwards))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_tree_test() {
+        let mut root = LayoutNode::View(0);
+        assert!(insert_before(&mut root, 0, 1, SplitDirection::Vertical));
+        assert!(insert_before(&mut root, 1, 2, SplitDirection::Horizontal));
+        assert_eq!(root, LayoutNode::Split(SplitDirection::Vertical, vec![
+            LayoutNode::Split(SplitDirection::Horizontal, vec![LayoutNode::View(2), LayoutNode::View(1)]),
+            LayoutNode::View(0)
+        ]));
+
+        let mut rects = Vec::new();
+        layout_rects(&root, Rect { x: 0.0, y: 0.0, width: 80.0, height: 24.0 }, &mut rects);
+        assert_eq!(rects[0], (2, Rect { x: 0.0, y: 0.0, width: 40.0, height: 12.0 }));
+        assert_eq!(rects[2], (0, Rect { x: 40.0, y: 0.0, width: 40.0, height: 24.0 }));
+
+        assert!(rotate(&mut root, 2, true));
+        assert_eq!(remove_view(root, 0), Some(LayoutNode::Split(SplitDirection::Horizontal, vec![
+            LayoutNode::View(1),
+            LayoutNode::View(2)
+        ])));
+    }
+}
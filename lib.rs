@@ -6,6 +6,58 @@ use std::{ os::raw::c_long, ffi::CString, ffi::CStr };
 #[allow(non_snake_case)]
 include!("./bindings.rs");
 
+mod register;
+mod clipboard;
+mod event;
+mod macros;
+mod goto;
+mod format;
+mod indent;
+mod comment;
+mod window;
+mod layout;
+mod tabs;
+mod viewport;
+mod screen_model;
+mod multi_cursor;
+mod terminal;
+mod shell;
+mod command;
+mod eval;
+mod input;
+mod key;
+mod feed;
+mod mapping;
+mod keymap;
+mod autocmd;
+mod option;
+
+pub use register::*;
+pub use clipboard::*;
+pub use event::*;
+pub use macros::*;
+pub use goto::*;
+pub use format::*;
+pub use indent::*;
+pub use comment::*;
+pub use window::*;
+pub use layout::*;
+pub use tabs::*;
+pub use viewport::*;
+pub use screen_model::*;
+pub use multi_cursor::*;
+pub use terminal::*;
+pub use shell::*;
+pub use command::*;
+pub use eval::*;
+pub use input::*;
+pub use key::*;
+pub use feed::*;
+pub use mapping::*;
+pub use keymap::*;
+pub use autocmd::*;
+pub use option::*;
+
 pub type VimBuffer = file_buffer;
 pub type CursorPosition = pos_T;
 
@@ -29,10 +81,64 @@ pub fn vim_get_mode() -> VimMode {
     }
 }
 
+pub(crate) unsafe fn string_from_vim(value: *const char_u) -> Option<String> {
+    if value.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(value as *const c_char).to_string_lossy().into_owned())
+    }
+}
+
+pub(crate) unsafe fn lines_from_vim(lines: *const *mut char_u, count: c_int) -> Vec<String> {
+    if lines.is_null() {
+        return Vec::new();
+    }
+    (0..count.max(0) as usize)
+        .map(|index| string_from_vim(*lines.add(index)).unwrap_or_default())
+        .collect()
+}
+
+// Reads a line without trusting it to be UTF-8, since callbacks must not
+// panic. Returns None for a line number outside the buffer.
+pub(crate) unsafe fn buffer_line(buffer: *mut buf_T, line_number: c_long) -> Option<String> {
+    if buffer.is_null() || line_number < 1 || line_number as usize > vimBufferGetLineCount(buffer) {
+        return None;
+    }
+    string_from_vim(vimBufferGetLine(buffer, line_number))
+}
+
+// Strings handed over to libvim are released with vim_free, so they have to
+// come from vim's own allocator. NUL bytes are stored as NL inside Vim lines.
+pub(crate) fn string_to_vim(value: &str) -> *mut char_u {
+    let value = CString::new(value.replace('\0', "\n")).unwrap();
+    unsafe { vim_strsave(value.as_ptr() as *mut char_u) }
+}
+
+pub(crate) fn lines_to_vim(lines: &[String]) -> *mut *mut char_u {
+    unsafe {
+        let array = alloc(std::mem::size_of::<*mut char_u>() * lines.len().max(1)) as *mut *mut char_u;
+        for (index, line) in lines.iter().enumerate() {
+            *array.add(index) = string_to_vim(line);
+        }
+        array
+    }
+}
+
+pub(crate) fn eval_string(expr: &str) -> Option<String> {
+    let expr = CString::new(expr).ok()?;
+    unsafe {
+        let result = vimEval(expr.as_ptr() as *mut char_u);
+        let value = string_from_vim(result);
+        vim_free(result as *mut std::os::raw::c_void);
+        value
+    }
+}
+
 pub fn vim_init() {
     // create a vector of zero terminated strings
     let mut args: Vec<*mut c_char> = std::env::args().into_iter().map(|arg| CString::new(arg).unwrap().into_raw() ).collect();
     unsafe { vimInit(args.len() as c_int, args.as_mut_ptr()); }
+    event::install_event_callbacks();
 }
 
 pub fn vim_execute_lines(cmds: &mut Vec<&str>) {
@@ -83,6 +189,21 @@ pub fn vim_buffer_get_id(buffer: &mut file_buffer) -> c_int {
     unsafe { vimBufferGetId(buffer as *mut file_buffer) }
 }
 
+/// Replaces the lines after `start` up to and including `end` (both line
+/// numbers, `end` of -1 meaning the last line), so `(0, 0)` inserts at the
+/// top and `(2, 4)` replaces lines 3 and 4.
+pub fn vim_buffer_set_lines(buffer: &mut file_buffer, start: c_long, end: c_long, lines: &[String]) {
+    let lines: Vec<CString> = lines.iter().map(|line| CString::new(line.replace('\0', "\n")).unwrap()).collect();
+    let mut raw_lines: Vec<*mut c_uchar> = lines.iter().map(|line| line.as_ptr() as *mut c_uchar).collect();
+    unsafe {
+        vimBufferSetLines(buffer as *mut file_buffer, start, end, raw_lines.as_mut_ptr(), raw_lines.len() as c_int);
+    }
+}
+
+pub fn vim_buffer_get_filetype(buffer: &mut file_buffer) -> Option<String> {
+    unsafe { string_from_vim(vimBufferGetFiletype(buffer as *mut file_buffer)).filter(|filetype| !filetype.is_empty()) }
+}
+
 pub fn vim_load_buffer(file_path: &str) -> Option<&mut file_buffer> {
     let file_path_c_string = CString::new(file_path).unwrap().into_raw() as *mut u8;
     unsafe { 
@@ -136,8 +257,10 @@ pub fn vim_set_window_size(size: (c_int, c_int)) {
 
 #[cfg(test)]
 mod tests {
+    use std::cell::RefCell;
     use std::fs;
     use std::path::Path;
+    use std::rc::Rc;
     use super::*;
 
     fn setup() {
@@ -152,10 +275,337 @@ mod tests {
     #[test]
     fn suite_test() {
         setup();
+        registers_test();
+        clipboard_test();
+        macro_recording_test();
+        viewport_test();
+        goto_test();
+        window_test();
+        eval_test();
+        feed_test();
+        getchar_test();
+        mappings_test();
+        native_mappings_test();
+        user_command_test();
+        options_test();
+        indent_test();
+        format_test();
+        buffer_mappings_test();
         navigation_and_file_creation_test();
         navigation_G_gg_test();
     }
 
+    fn registers_test() {
+        let register = Register::new(vec!["Good news, everyone!".to_string()], RegisterKind::Line);
+        assert!(vim_register_set('a', &register));
+        assert_eq!(vim_register_get('a'), Some(register.clone()));
+        assert_eq!(vim_register_get('A'), Some(register));
+
+        let search = Register::new(vec!["Nibbler".to_string()], RegisterKind::Char);
+        assert!(vim_register_set('/', &search));
+        assert_eq!(vim_register_get('/').map(|register| register.lines), Some(search.lines.clone()));
+
+        let two_lines = Register::new(vec!["1 +".to_string(), "1".to_string()], RegisterKind::Char);
+        assert!(!vim_register_set('=', &two_lines));
+        assert!(!vim_register_set('!', &search));
+    }
+
+    fn clipboard_test() {
+        let clipboard = MemoryClipboard::new();
+        vim_set_clipboard_provider(clipboard.clone());
+        vim_input("iPlanet Express");
+        vim_key("<Esc>");
+        vim_feed("\"+yy");
+        assert_eq!(clipboard.contents('+'), Some(Register::new(vec!["Planet Express".to_string()], RegisterKind::Line)));
+
+        clipboard.set_contents('+', Register::new(vec!["Slurm".to_string()], RegisterKind::Line));
+        vim_feed("\"+p");
+        let buffer = unsafe { &mut *vimBufferGetCurrent() };
+        assert_eq!(vim_buffer_get_line(buffer, 2), Some("Slurm"));
+
+        vim_clear_clipboard_provider();
+        vim_feed("uu");
+        assert_eq!(vim_buffer_line_count(buffer), 1);
+    }
+
+    fn macro_recording_test() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorded = events.clone();
+        let listener = vim_add_event_listener(move |event| recorded.borrow_mut().push(event.clone()));
+
+        vim_input("q");
+        vim_input("q");
+        assert_eq!(vim_macro_recording(), Some('q'));
+        vim_input("i");
+        vim_input("x");
+        vim_key("<esc>");
+        vim_input("q");
+        assert_eq!(vim_macro_recording(), None);
+        vim_input("u");
+        assert_eq!(*events.borrow(), vec![
+            VimEvent::MacroRecordingStarted { register: 'q' },
+            VimEvent::MacroRecordingStopped { register: 'q', keys: "ix<Esc>".to_string() }
+        ]);
+
+        assert!(vim_macro_set('w', "Ahello<Esc>"));
+        assert!(!vim_macro_set('%', "Ahello<Esc>"));
+        assert!(!vim_macro_set('w', "A\0<Esc>"));
+        assert_eq!(vim_macro_get('w').expect("Read macro w"), "Ahello<Esc>");
+        vim_remove_event_listener(listener);
+    }
+
+    fn viewport_test() {
+        let viewport = Viewport { top_line: 1, left_column: 0, width: 80, height: 24 };
+        vim_set_viewport(viewport);
+        assert_eq!(vim_get_viewport(), viewport);
+        let directions = Rc::new(RefCell::new(Vec::new()));
+        let recorded = directions.clone();
+        let listener = vim_add_event_listener(move |event| if let VimEvent::Scroll { direction, .. } = event {
+            recorded.borrow_mut().push(*direction);
+        });
+        vim_feed("zz");
+        vim_feed("<C-e>");
+        vim_remove_event_listener(listener);
+        assert_eq!(*directions.borrow(), vec![
+            ScrollDirection::CursorCenterVertically,
+            ScrollDirection::LineDown
+        ]);
+        vim_set_window_size((1024, 768));
+    }
+
+    fn goto_test() {
+        let requests = Rc::new(RefCell::new(Vec::new()));
+        let recorded = requests.clone();
+        vim_set_goto_handler(move |request| {
+            recorded.borrow_mut().push(request.target);
+            true
+        });
+        vim_feed("gd");
+        vim_feed("gD");
+        vim_clear_goto_handler();
+        assert_eq!(*requests.borrow(), vec![GotoTarget::Definition, GotoTarget::Declaration]);
+    }
+
+    fn window_test() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorded = events.clone();
+        let listener = vim_add_event_listener(move |event| match event {
+            VimEvent::WindowSplit { .. } | VimEvent::WindowMoved { .. } => recorded.borrow_mut().push(event.clone()),
+            _ => ()
+        });
+        vim_feed("<C-w>v");
+        vim_feed("2<C-w>l");
+        vim_remove_event_listener(listener);
+        assert_eq!(*events.borrow(), vec![
+            VimEvent::WindowSplit { kind: SplitKind::Vertical, filename: None },
+            VimEvent::WindowMoved { movement: WindowMove::CursorRight, count: 2 }
+        ]);
+    }
+
+    fn eval_test() {
+        vim_execute("set shiftwidth=3");
+        assert_eq!(vim_eval("&shiftwidth"), Ok(VimValue::Number(3)));
+        assert_eq!(vim_eval("1.5"), Ok(VimValue::Float(1.5)));
+        assert_eq!(vim_eval("0z0102"), Ok(VimValue::Blob(vec![1, 2])));
+        assert_eq!(vim_eval("['Zoidberg', v:true, v:null]"), Ok(VimValue::List(vec![
+            VimValue::String("Zoidberg".to_string()),
+            VimValue::Bool(true),
+            VimValue::Null
+        ])));
+        assert!(vim_eval("function('tr')").is_err());
+    }
+
+    fn options_test() {
+        assert_eq!(VimOption::SHIFTWIDTH.set(4, OptionScope::Both), Ok(()));
+        assert_eq!(vim_option::<i64>("sw"), Ok(4));
+        assert_eq!(vim_eval("&shiftwidth"), Ok(VimValue::Number(4)));
+
+        assert_eq!(vim_set_option("wrap", false, OptionScope::Local), Ok(()));
+        assert_eq!(VimOption::WRAP.get(), Ok(false));
+        assert_eq!(VimOption::WRAP.set(true, OptionScope::Local), Ok(()));
+        assert_eq!(VimOption::WRAP.get(), Ok(true));
+
+        assert_eq!(VimOption::COMMENTSTRING.set("# %s".to_string(), OptionScope::Local), Ok(()));
+        assert_eq!(vim_option::<String>("cms"), Ok("# %s".to_string()));
+        assert!(matches!(
+            VimOption::COMMENTSTRING.set("hypnotoad".to_string(), OptionScope::Local),
+            Err(OptionError::Invalid { .. })
+        ));
+
+        assert!(matches!(vim_option::<i64>("zoidberg"), Err(OptionError::Unknown { .. })));
+        assert!(matches!(vim_set_option("shiftwidth", "8".to_string(), OptionScope::Both), Err(OptionError::WrongType { .. })));
+        assert!(matches!(vim_set_option("sw", true, OptionScope::Both), Err(OptionError::WrongType { .. })));
+        assert_eq!(VimOption::SHIFTWIDTH.get(), Ok(4));
+
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let recorded = changes.clone();
+        let listener = vim_add_event_listener(move |event| {
+            if let VimEvent::OptionChanged { short_name, value, scope, .. } = event {
+                recorded.borrow_mut().push((short_name.clone(), value.clone(), *scope));
+            }
+        });
+        vim_execute("setlocal nowrap");
+        vim_execute("set sw=2");
+        vim_remove_event_listener(listener);
+        assert_eq!(*changes.borrow(), vec![
+            ("wrap".to_string(), OptionValue::Bool(false), OptionScope::Local),
+            ("sw".to_string(), OptionValue::Number(2), OptionScope::Both)
+        ]);
+    }
+
+    fn indent_test() {
+        vim_set_indent_provider(|context: &IndentContext| {
+            let opens = context.previous_line.trim_end().ends_with('{');
+            IndentAction::Absolute(if opens { context.shiftwidth as c_int } else { 0 })
+        });
+        vim_input("oif {\rx\ry");
+        vim_key("<Esc>");
+        vim_clear_indent_provider();
+
+        let buffer = unsafe { &mut *vimBufferGetCurrent() };
+        let lines: Vec<String> = (2..=4)
+            .map(|line| vim_buffer_get_line(buffer, line).unwrap_or_default().to_string())
+            .collect();
+        assert_eq!(lines, vec!["if {", "  x", "y"]);
+        vim_input("u");
+        assert_eq!(vim_buffer_line_count(buffer), 1);
+    }
+
+    fn format_test() {
+        vim_set_formatter(|request: &FormatRequest| match request.kind {
+            FormatKind::Formatting => Some(vec![request.lines.join(" ")]),
+            FormatKind::Indentation => Some(request.lines.iter().map(|line| format!("  {}", line.to_uppercase())).collect())
+        });
+        vim_input("ofoo\rbar");
+        vim_key("<Esc>");
+
+        let buffer = unsafe { &mut *vimBufferGetCurrent() };
+        vim_feed("kgqj");
+        assert_eq!(vim_buffer_line_count(buffer), 2);
+        assert_eq!(vim_buffer_get_line(buffer, 2), Some("foo bar"));
+        assert_eq!(vim_cursor_get_line(), 2);
+
+        vim_feed("obaz<Esc>k=j");
+        assert_eq!(vim_buffer_get_line(buffer, 2), Some("  FOO BAR"));
+        assert_eq!(vim_buffer_get_line(buffer, 3), Some("  BAZ"));
+        let cursor = vim_cursor_get_position();
+        assert_eq!((cursor.lnum, cursor.col), (2, 2));
+
+        vim_clear_formatter();
+        vim_feed("uuuu");
+        assert_eq!(vim_buffer_line_count(buffer), 1);
+    }
+
+    fn feed_test() {
+        let result = vim_feed("ohello<Esc>");
+        assert_eq!(result.mode, VimMode::Normal);
+        assert_eq!((result.cursor.lnum, result.cursor.col), (2, 4));
+        assert_eq!(result.changed_buffers, vec![vim_buffer_get_id(unsafe { &mut *vimBufferGetCurrent() })]);
+        assert!(result.selection.is_none());
+
+        let result = vim_feed("Vk");
+        assert_eq!(result.mode, VimMode::Visual);
+        let selection = result.selection.expect("Read visual selection");
+        assert_eq!(selection.kind, RegisterKind::Line);
+        assert_eq!((selection.start.lnum, selection.end.lnum), (1, 2));
+        assert!(result.changed_buffers.is_empty());
+
+        vim_feed("<Esc>u");
+    }
+
+    fn getchar_test() {
+        let queue = QueueInput::new();
+        queue.push_str("abc");
+        let modes = Rc::new(RefCell::new(Vec::new()));
+        let recorded = modes.clone();
+        let mut source = queue.clone();
+        vim_set_input_source(move |mode| {
+            recorded.borrow_mut().push(mode);
+            source.get_char(mode)
+        });
+        assert_eq!(vim_eval("getchar()"), Ok(VimValue::Number('a' as i64)));
+        assert_eq!(vim_eval("getchar(0)"), Ok(VimValue::Number('b' as i64)));
+        assert_eq!(vim_eval("getchar(1)"), Ok(VimValue::Number('c' as i64)));
+        vim_clear_input_source();
+        assert_eq!(*modes.borrow(), vec![GetCharMode::Wait, GetCharMode::NoWait, GetCharMode::Peek]);
+        assert_eq!(queue.len(), 1);
+    }
+
+    fn mappings_test() {
+        vim_execute("nnoremap <silent> <C-a> :echo 'Bender'<CR>");
+        let mapping = vim_mappings().into_iter()
+            .find(|mapping| mapping.lhs == "<C-a>")
+            .expect("Find <C-a> mapping");
+        assert_eq!(mapping.mode, MapMode::NORMAL);
+        assert_eq!(mapping.rhs, ":echo 'Bender'<CR>");
+        assert!(mapping.noremap && mapping.silent && !mapping.expr);
+        assert_eq!(mapping.buffer_local, None);
+
+        vim_execute("xmap <nowait> <Space>\\| <Esc>");
+        let summary = |mappings: Vec<Mapping>| {
+            let mut summary: Vec<_> = mappings.into_iter()
+                .map(|mapping| (canonical_keys(&mapping.lhs), mapping.mode, mapping.rhs, mapping.noremap, mapping.silent, mapping.nowait))
+                .collect();
+            summary.sort_by(|left, right| left.0.cmp(&right.0));
+            summary
+        };
+        let recorded = summary(vim_mappings());
+        seed_mappings();
+        assert_eq!(summary(vim_mappings()), recorded);
+        assert!(vim_mappings().iter().any(|mapping| mapping.mode == MapMode::VISUAL && mapping.nowait && mapping.rhs == "<Esc>"));
+
+        vim_execute("xunmap <Space>\\|");
+        vim_execute("nunmap <c-a>");
+        assert!(vim_mappings().iter().all(|mapping| mapping.lhs != "<C-a>"));
+    }
+
+    fn native_mappings_test() {
+        let triggered = Rc::new(RefCell::new(Vec::new()));
+        let recorded = triggered.clone();
+        assert!(vim_map(MapMode::NORMAL | MapMode::VISUAL, "<F5>", move |context| recorded.borrow_mut().push(*context)));
+        assert!(!vim_map(MapMode::COMMAND_LINE, "<F5>", |_| {}));
+        vim_input(":LibvimRs");
+        assert!(vim_command_line_completions().iter().all(|completion| !completion.starts_with("LibvimRs")));
+        vim_key("<Esc>");
+
+        vim_feed("3<F5>");
+        assert_eq!(vim_get_mode(), VimMode::Normal);
+        assert_eq!(triggered.borrow().last().map(|context| (context.mode, context.count)), Some((MapMode::NORMAL, 3)));
+
+        vim_unmap(MapMode::NORMAL | MapMode::VISUAL, "<F5>");
+        vim_feed("<F5>");
+        assert_eq!(triggered.borrow().len(), 1);
+        vim_feed("<Esc>");
+    }
+
+    fn user_command_test() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let recorded = calls.clone();
+        assert!(vim_register_command(UserCommand::new("Hypnotoad", move |args| {
+            recorded.borrow_mut().push(args.clone());
+            if args.args == "fail" { Err("All glory to the Hypnotoad".to_string()) } else { Ok(()) }
+        })));
+        vim_input("ia\rb\rc\rd");
+        vim_key("<Esc>");
+        vim_execute("3,4Hypnotoad! x");
+        vim_execute("let v:errmsg = ''");
+        vim_execute("Hypnotoad fail");
+        assert_eq!(vim_eval("v:errmsg"), Ok(VimValue::String("All glory to the Hypnotoad".to_string())));
+        assert!(vim_unregister_command("Hypnotoad"));
+        vim_input("u");
+
+        let calls = calls.borrow();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0], CommandArgs {
+            name: "Hypnotoad".to_string(),
+            bang: true,
+            range: Some(CommandRange { start: 3, end: 4 }),
+            register: None,
+            args: "x".to_string()
+        });
+        assert!(!calls[1].bang && calls[1].range.is_none());
+    }
+
     fn navigation_G_gg_test() {
         let buffer = vim_buffer_open("./test/futurama-quotes.txt").expect("Read file as buffer");
         let quote = "    Leela: Oh, I'm sorry. Now I'll axe you again. Where is the mi-cro-wave?";
@@ -183,6 +633,17 @@ mod tests {
         teardown();
     }
 
+    // Uses the buffer navigation_and_file_creation_test edits next, so the
+    // buffer numbers the navigation tests expect stay the same.
+    fn buffer_mappings_test() {
+        vim_execute("e ./test/file_test");
+        let buffer_id = vim_buffer_get_id(unsafe { &mut *vimBufferGetCurrent() });
+        vim_execute("nnoremap <buffer> Q x");
+        assert!(vim_mappings().iter().any(|mapping| mapping.lhs == "Q" && mapping.buffer_local == Some(buffer_id)));
+        vim_execute("bdelete!");
+        assert!(vim_mappings().iter().all(|mapping| mapping.buffer_local != Some(buffer_id)));
+    }
+
     fn navigation_and_file_creation_test() {
         let filename = "./test/file_test";
         if Path::new(filename).exists() {
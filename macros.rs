@@ -0,0 +1,91 @@
+use libc::c_int;
+use std::cell::Cell;
+use std::ffi::CString;
+use std::os::raw::c_void;
+use std::ptr;
+
+use crate::event::{ emit, VimEvent };
+use crate::register::is_yank_register;
+use crate::{
+    char_u, get_reg_contents, replace_termcodes, str2special_save, string_from_vim,
+    vimMacroSetStartRecordCallback, vimMacroSetStopRecordCallback, vim_execute, vim_free,
+    write_reg_contents, GREG_NO_EXPR
+};
+
+thread_local! {
+    static RECORDING: Cell<Option<char>> = const { Cell::new(None) };
+}
+
+pub(crate) fn install_callbacks() {
+    unsafe {
+        vimMacroSetStartRecordCallback(Some(macro_start_record));
+        vimMacroSetStopRecordCallback(Some(macro_stop_record));
+    }
+}
+
+/// The register a `q<reg>` recording is currently going into, for a
+/// "recording @q" indicator.
+pub fn vim_macro_recording() -> Option<char> {
+    RECORDING.with(|recording| recording.get())
+}
+
+/// Returns the keys stored in a register as key notation, e.g. `ifoo<Esc>`.
+pub fn vim_macro_get(register: char) -> Option<String> {
+    unsafe {
+        let contents = get_reg_contents(register as c_int, GREG_NO_EXPR as c_int);
+        if contents.is_null() {
+            return None;
+        }
+        let keys = keys_to_notation(contents);
+        vim_free(contents as *mut c_void);
+        Some(keys)
+    }
+}
+
+/// Stores keys written in key notation into a register so `@<reg>` replays
+/// them. Returns false when `register` can't hold a macro or `keys` contains
+/// a NUL byte.
+pub fn vim_macro_set(register: char, keys: &str) -> bool {
+    if !is_yank_register(register) {
+        return false;
+    }
+    let keys = match CString::new(keys) {
+        Ok(keys) => keys,
+        Err(_) => return false
+    };
+    unsafe {
+        let mut raw: *mut char_u = ptr::null_mut();
+        let translated = replace_termcodes(keys.as_ptr() as *mut char_u, &mut raw, 0, 1, 0);
+        write_reg_contents(register as c_int, translated, -1, 0);
+        vim_free(raw as *mut c_void);
+    }
+    true
+}
+
+pub fn vim_macro_replay(register: char, count: usize) {
+    vim_execute(&format!("normal! {}@{}", count.max(1), register));
+}
+
+pub(crate) unsafe fn keys_to_notation(keys: *mut char_u) -> String {
+    let notation = str2special_save(keys, 0);
+    let value = string_from_vim(notation).unwrap_or_default();
+    vim_free(notation as *mut c_void);
+    value
+}
+
+fn register_name(regname: c_int) -> char {
+    char::from_u32(regname as u32).unwrap_or('"')
+}
+
+unsafe extern "C" fn macro_start_record(regname: c_int) {
+    let register = register_name(regname);
+    RECORDING.with(|recording| recording.set(Some(register)));
+    emit(VimEvent::MacroRecordingStarted { register });
+}
+
+unsafe extern "C" fn macro_stop_record(regname: c_int, regvalue: *mut char_u) {
+    let register = register_name(regname);
+    RECORDING.with(|recording| recording.set(None));
+    let keys = if regvalue.is_null() { String::new() } else { keys_to_notation(regvalue) };
+    emit(VimEvent::MacroRecordingStopped { register, keys });
+}
@@ -0,0 +1,302 @@
+use libc::c_int;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::ops::BitOr;
+use std::os::raw::c_void;
+use std::ptr;
+
+use crate::event::{ emit, VimEvent };
+use crate::macros::keys_to_notation;
+use crate::{
+    buf_T, char_u, eval_string, mapblock_T, replace_termcodes, string_from_vim, vimBufferGetCurrent,
+    vimBufferGetId, vimSetInputMapCallback, vimSetInputUnmapCallback, vim_add_event_listener, vim_eval,
+    vim_free, AutoCmdEvent, VimValue, CMDLINE, INSERT, LANGMAP, NORMAL, OP_PENDING, SELECTMODE, TERMINAL,
+    VISUAL
+};
+
+/// The modes a mapping applies to, as a set.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct MapMode(c_int);
+
+impl MapMode {
+    pub const NONE: MapMode = MapMode(0);
+    pub const NORMAL: MapMode = MapMode(NORMAL as c_int);
+    /// Visual mode only, like `:xmap`.
+    pub const VISUAL: MapMode = MapMode(VISUAL as c_int);
+    pub const SELECT: MapMode = MapMode(SELECTMODE as c_int);
+    pub const OPERATOR_PENDING: MapMode = MapMode(OP_PENDING as c_int);
+    pub const INSERT: MapMode = MapMode(INSERT as c_int);
+    pub const COMMAND_LINE: MapMode = MapMode(CMDLINE as c_int);
+    /// `:lmap`
+    pub const LANG_ARG: MapMode = MapMode(LANGMAP as c_int);
+    pub const TERMINAL: MapMode = MapMode(TERMINAL as c_int);
+
+    pub fn from_bits(bits: c_int) -> MapMode {
+        MapMode(bits)
+    }
+
+    pub fn bits(self) -> c_int {
+        self.0
+    }
+
+    pub fn contains(self, other: MapMode) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(self, other: MapMode) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn without(self, other: MapMode) -> MapMode {
+        MapMode(self.0 & !other.0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for MapMode {
+    type Output = MapMode;
+
+    fn bitor(self, other: MapMode) -> MapMode {
+        MapMode(self.0 | other.0)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Mapping {
+    pub mode: MapMode,
+    /// The keys as written in the `:map` command, e.g. `<Leader>f`.
+    pub lhs: String,
+    pub rhs: String,
+    pub noremap: bool,
+    pub silent: bool,
+    pub nowait: bool,
+    pub expr: bool,
+    /// The id of the buffer a `<buffer>` mapping belongs to.
+    pub buffer_local: Option<c_int>,
+    pub script_id: c_int,
+    // The keys in a canonical notation, so `<c-a>` and `<C-A>` compare equal.
+    keys: String
+}
+
+thread_local! {
+    static MAPPINGS: RefCell<Vec<Mapping>> = const { RefCell::new(Vec::new()) };
+}
+
+pub(crate) fn install_callbacks() {
+    unsafe {
+        vimSetInputMapCallback(Some(input_map));
+        vimSetInputUnmapCallback(Some(input_unmap));
+    }
+    seed_mappings();
+    // Deleting or wiping out a buffer clears its local mappings without
+    // calling the unmap callback.
+    vim_add_event_listener(|event| if let VimEvent::AutoCommand(autocmd) = event {
+        if autocmd.event == AutoCmdEvent::BufDelete || autocmd.event == AutoCmdEvent::BufWipeout {
+            MAPPINGS.with(|mappings| {
+                mappings.borrow_mut().retain(|mapping| mapping.buffer_local != Some(autocmd.buffer_id));
+            });
+        }
+    });
+}
+
+/// Every mapping libvim has, buffer-local ones included. Those defined while
+/// `vim_init` ran, e.g. by a vimrc, are included too.
+pub fn vim_mappings() -> Vec<Mapping> {
+    MAPPINGS.with(|mappings| mappings.borrow().clone())
+}
+
+/// The mappings that apply in `mode` in the buffer `buffer_id`, with
+/// buffer-local mappings hiding global ones for the same keys.
+pub fn vim_mappings_for(mode: MapMode, buffer_id: c_int) -> Vec<Mapping> {
+    let mappings = vim_mappings();
+    let local: Vec<&Mapping> = mappings.iter()
+        .filter(|mapping| mapping.buffer_local == Some(buffer_id) && mapping.mode.intersects(mode))
+        .collect();
+    let global = mappings.iter()
+        .filter(|mapping| mapping.buffer_local.is_none() && mapping.mode.intersects(mode))
+        .filter(|mapping| !local.iter().any(|local| local.keys == mapping.keys));
+    local.iter().copied().chain(global).cloned().collect()
+}
+
+pub(crate) fn canonical_keys(keys: &str) -> String {
+    let keys = match CString::new(keys) {
+        Ok(keys) => keys,
+        Err(_) => return String::new()
+    };
+    unsafe {
+        let mut raw: *mut char_u = ptr::null_mut();
+        let translated = replace_termcodes(keys.as_ptr() as *mut char_u, &mut raw, 0, 1, 0);
+        let canonical = keys_to_notation(translated);
+        vim_free(raw as *mut c_void);
+        canonical
+    }
+}
+
+// Replaces the registry with the mappings listed by `:map` and its variants,
+// for those defined before the callbacks were installed. Each listed key
+// sequence is looked up with `maparg()` in every mode it could apply to.
+pub(crate) fn seed_mappings() {
+    let buffer_id = unsafe {
+        let buffer = vimBufferGetCurrent();
+        if buffer.is_null() { None } else { Some(vimBufferGetId(buffer)) }
+    };
+    let mut seeded: Vec<Mapping> = Vec::new();
+    for listing in ["map", "map!", "lmap", "tmap"] {
+        let listed = eval_string(&format!("execute('{}')", listing)).unwrap_or_default();
+        for lhs in listed.lines().filter_map(listed_lhs) {
+            for mode in ["n", "x", "s", "o", "i", "c", "l", "t"] {
+                let expression = format!("maparg('{}', '{}', 0, 1)", lhs.replace('\'', "''"), mode);
+                let mapping = match vim_eval(&expression) {
+                    Ok(VimValue::Dict(info)) => mapping_from_maparg(&info, buffer_id),
+                    _ => None
+                };
+                if let Some(mapping) = mapping.filter(|mapping| !seeded.contains(mapping)) {
+                    seeded.push(mapping);
+                }
+            }
+        }
+    }
+    MAPPINGS.with(|mappings| *mappings.borrow_mut() = seeded);
+}
+
+// A `:map` listing line starts with the modes in a column three wide,
+// followed by the keys, which show spaces as `<Space>`.
+fn listed_lhs(line: &str) -> Option<&str> {
+    line.get(3..)?.split(' ').next().filter(|lhs| !lhs.is_empty())
+}
+
+fn mapping_from_maparg(info: &BTreeMap<String, VimValue>, buffer_id: Option<c_int>) -> Option<Mapping> {
+    let text = |key: &str| info.get(key).and_then(VimValue::as_str).map(String::from);
+    let flag = |key: &str| info.get(key).and_then(VimValue::as_number).is_some_and(|value| value != 0);
+    let lhs = text("lhs")?;
+    Some(Mapping {
+        mode: map_mode_from_chars(&text("mode")?),
+        rhs: text("rhs").unwrap_or_default(),
+        noremap: flag("noremap"),
+        silent: flag("silent"),
+        nowait: flag("nowait"),
+        expr: flag("expr"),
+        buffer_local: if flag("buffer") { buffer_id } else { None },
+        script_id: info.get("sid").and_then(VimValue::as_number).unwrap_or(0) as c_int,
+        keys: canonical_keys(&lhs),
+        lhs
+    })
+}
+
+// The modes as `maparg()` and `:map` show them, e.g. `nv` or `!`.
+fn map_mode_from_chars(chars: &str) -> MapMode {
+    chars.chars().fold(MapMode::NONE, |mode, char| mode | match char {
+        ' ' => MapMode::NORMAL | MapMode::VISUAL | MapMode::SELECT | MapMode::OPERATOR_PENDING,
+        '!' => MapMode::INSERT | MapMode::COMMAND_LINE,
+        'n' => MapMode::NORMAL,
+        'v' => MapMode::VISUAL | MapMode::SELECT,
+        'x' => MapMode::VISUAL,
+        's' => MapMode::SELECT,
+        'o' => MapMode::OPERATOR_PENDING,
+        'i' => MapMode::INSERT,
+        'c' => MapMode::COMMAND_LINE,
+        'l' => MapMode::LANG_ARG,
+        't' => MapMode::TERMINAL,
+        _ => MapMode::NONE
+    })
+}
+
+unsafe fn any_buffer_mapping(buffer: *mut buf_T, matches: impl Fn(*mut mapblock_T) -> bool) -> bool {
+    if buffer.is_null() {
+        return false;
+    }
+    (*buffer).b_maphash.iter().any(|&bucket| {
+        let mut current = bucket;
+        while !current.is_null() {
+            if matches(current) {
+                return true;
+            }
+            current = (*current).m_next;
+        }
+        false
+    })
+}
+
+unsafe extern "C" fn input_map(mapping: *const mapblock_T) {
+    if mapping.is_null() {
+        return;
+    }
+    let block = &*mapping;
+    let buffer = vimBufferGetCurrent();
+    let buffer_local = if any_buffer_mapping(buffer, |current| ptr::eq(current, mapping)) {
+        Some(vimBufferGetId(buffer))
+    } else {
+        None
+    };
+    let keys = keys_to_notation(block.m_keys);
+    let mapping = Mapping {
+        mode: MapMode(block.m_mode),
+        lhs: string_from_vim(block.m_orig_keys).unwrap_or_else(|| keys.clone()),
+        rhs: string_from_vim(block.m_orig_str).unwrap_or_else(|| keys_to_notation(block.m_str)),
+        noremap: block.m_noremap != 0,
+        silent: block.m_silent != 0,
+        nowait: block.m_nowait != 0,
+        expr: block.m_expr != 0,
+        buffer_local,
+        script_id: block.m_script_ctx.sc_sid,
+        keys
+    };
+    MAPPINGS.with(|mappings| {
+        let mut mappings = mappings.borrow_mut();
+        for existing in mappings.iter_mut() {
+            if existing.keys == mapping.keys && existing.buffer_local == mapping.buffer_local {
+                existing.mode = existing.mode.without(mapping.mode);
+            }
+        }
+        mappings.retain(|existing| !existing.mode.is_empty());
+        mappings.push(mapping.clone());
+    });
+    emit(VimEvent::MappingAdded(mapping));
+}
+
+// The callback does not say whether `<buffer>` was given, so a mapping local
+// to the current buffer is only taken to be the one removed once it has
+// disappeared from the buffer's own table.
+unsafe extern "C" fn input_unmap(mode: c_int, orig_lhs: *const char_u) {
+    let lhs = string_from_vim(orig_lhs).unwrap_or_default();
+    let keys = canonical_keys(&lhs);
+    let mode = MapMode(mode);
+    let buffer = vimBufferGetCurrent();
+    let buffer_id = if buffer.is_null() { None } else { Some(vimBufferGetId(buffer)) };
+    let local_still_mapped = any_buffer_mapping(buffer, |current| {
+        MapMode((*current).m_mode).intersects(mode) && keys_to_notation((*current).m_keys) == keys
+    });
+    MAPPINGS.with(|mappings| {
+        let mut mappings = mappings.borrow_mut();
+        let local_removed = !local_still_mapped && buffer_id.is_some() && mappings.iter().any(|mapping| {
+            mapping.keys == keys && mapping.mode.intersects(mode) && mapping.buffer_local == buffer_id
+        });
+        let target = if local_removed { buffer_id } else { None };
+        for mapping in mappings.iter_mut() {
+            if mapping.keys == keys && mapping.buffer_local == target {
+                mapping.mode = mapping.mode.without(mode);
+            }
+        }
+        mappings.retain(|mapping| !mapping.mode.is_empty());
+    });
+    emit(VimEvent::MappingRemoved { mode, lhs });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_listing_test() {
+        assert_eq!(listed_lhs("n  <C-A>       * :echo 'Bender'<CR>"), Some("<C-A>"));
+        assert_eq!(listed_lhs("   gx            <Plug>NetrwBrowseX"), Some("gx"));
+        assert_eq!(listed_lhs(""), None);
+        assert_eq!(map_mode_from_chars(" "), MapMode::NORMAL | MapMode::VISUAL | MapMode::SELECT | MapMode::OPERATOR_PENDING);
+        assert_eq!(map_mode_from_chars("!"), MapMode::INSERT | MapMode::COMMAND_LINE);
+        assert_eq!(map_mode_from_chars("nx"), MapMode::NORMAL | MapMode::VISUAL);
+    }
+}
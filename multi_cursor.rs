@@ -0,0 +1,209 @@
+use libc::c_long;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::event::HandlerSlot;
+use crate::{
+    pos_T, vimBufferGetCurrent, vimBufferGetLineCount, vimCursorGetPosition, vimCursorSetPosition,
+    vimSetCursorAddCallback, vim_get_mode, vim_input, vim_key, CursorPosition, VimMode
+};
+
+thread_local! {
+    static HANDLER: HandlerSlot<dyn FnMut(CursorPosition)> = const { HandlerSlot::new() };
+}
+
+/// Called for every cursor a multi-cursor command (such as a visual block
+/// insert) wants to add. Once a handler is set, libvim leaves replicating the
+/// edit at those cursors to the host.
+pub fn vim_set_cursor_add_handler<F: FnMut(CursorPosition) + 'static>(handler: F) {
+    HANDLER.with(|slot| slot.set(Some(Box::new(handler))));
+    unsafe { vimSetCursorAddCallback(Some(cursor_add)); }
+}
+
+pub fn vim_clear_cursor_add_handler() {
+    HANDLER.with(|slot| slot.set(None));
+    unsafe { vimSetCursorAddCallback(None); }
+}
+
+unsafe extern "C" fn cursor_add(cursor: pos_T) {
+    HANDLER.with(|slot| slot.call(|handler| handler(cursor)));
+}
+
+/// Secondary cursors next to libvim's own. Insert mode input sent through
+/// `input` and `key` is replayed at every cursor, and the cursors are dropped
+/// when insert mode ends.
+#[derive(Debug, Default)]
+pub struct MultiCursor {
+    cursors: Vec<CursorPosition>,
+    attached: bool
+}
+
+impl MultiCursor {
+    pub fn new() -> MultiCursor {
+        MultiCursor::default()
+    }
+
+    /// Creates a multi-cursor state that collects the cursors libvim adds.
+    pub fn attach() -> Rc<RefCell<MultiCursor>> {
+        let multi_cursor = Rc::new(RefCell::new(MultiCursor { cursors: Vec::new(), attached: true }));
+        let handler_cursor = Rc::downgrade(&multi_cursor);
+        // Cursors added while the host has the state borrowed are kept until
+        // the next one arrives.
+        let mut held = Vec::new();
+        vim_set_cursor_add_handler(move |cursor| {
+            let multi_cursor = match handler_cursor.upgrade() {
+                Some(multi_cursor) => multi_cursor,
+                None => return
+            };
+            held.push(cursor);
+            if let Ok(mut multi_cursor) = multi_cursor.try_borrow_mut() {
+                for cursor in held.drain(..) {
+                    multi_cursor.add(cursor);
+                }
+            };
+        });
+        multi_cursor
+    }
+
+    /// Stops collecting the cursors libvim adds.
+    pub fn detach(&mut self) {
+        if std::mem::take(&mut self.attached) {
+            vim_clear_cursor_add_handler();
+        }
+    }
+
+    pub fn add(&mut self, cursor: CursorPosition) {
+        let primary = unsafe { vimCursorGetPosition() };
+        let exists = self.cursors.iter().chain(std::iter::once(&primary))
+            .any(|existing| same_position(existing, &cursor));
+        if !exists {
+            self.cursors.push(cursor);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cursors.clear();
+    }
+
+    pub fn secondary_cursors(&self) -> &[CursorPosition] {
+        &self.cursors
+    }
+
+    /// Every cursor, libvim's own included, in buffer order.
+    pub fn cursors(&self) -> Vec<CursorPosition> {
+        let mut cursors = self.cursors.clone();
+        cursors.push(unsafe { vimCursorGetPosition() });
+        cursors.sort_by_key(|cursor| (cursor.lnum, cursor.col));
+        cursors
+    }
+
+    pub fn input(&mut self, text: &str) {
+        self.replay(|| vim_input(text));
+    }
+
+    pub fn key(&mut self, key: &str) {
+        self.replay(|| vim_key(key));
+    }
+
+    // Cursors are visited from the bottom of the buffer up, and the ones
+    // already visited are shifted by each edit made above them.
+    fn replay(&mut self, send: impl Fn()) {
+        if self.cursors.is_empty() || vim_get_mode() != VimMode::Insert {
+            send();
+            return;
+        }
+        let primary = unsafe { vimCursorGetPosition() };
+        let mut cursors: Vec<(bool, CursorPosition)> = self.cursors.iter()
+            .map(|cursor| (false, *cursor))
+            .chain(std::iter::once((true, primary)))
+            .collect();
+        cursors.sort_by_key(|(_, cursor)| std::cmp::Reverse((cursor.lnum, cursor.col)));
+
+        for index in 0..cursors.len() {
+            let before = cursors[index].1;
+            let (line_count, after) = unsafe {
+                let line_count = buffer_line_count();
+                vimCursorSetPosition(before);
+                send();
+                (buffer_line_count() - line_count, vimCursorGetPosition())
+            };
+            cursors[index].1 = after;
+            for (_, cursor) in cursors[..index].iter_mut() {
+                *cursor = shift_cursor(*cursor, before, after, line_count);
+            }
+            if vim_get_mode() != VimMode::Insert {
+                break;
+            }
+        }
+
+        if let Some((_, primary)) = cursors.iter().find(|(is_primary, _)| *is_primary) {
+            unsafe { vimCursorSetPosition(*primary); }
+        }
+        if vim_get_mode() == VimMode::Insert {
+            self.cursors = cursors.into_iter()
+                .filter(|(is_primary, _)| !is_primary)
+                .map(|(_, cursor)| cursor)
+                .collect();
+        } else {
+            self.clear();
+        }
+    }
+}
+
+impl Drop for MultiCursor {
+    fn drop(&mut self) {
+        self.detach();
+    }
+}
+
+unsafe fn buffer_line_count() -> c_long {
+    vimBufferGetLineCount(vimBufferGetCurrent()) as c_long
+}
+
+fn same_position(a: &CursorPosition, b: &CursorPosition) -> bool {
+    a.lnum == b.lnum && a.col == b.col
+}
+
+// Moves `cursor` to follow an edit that took another cursor from `before` to
+// `after` and changed the line count by `line_delta`.
+fn shift_cursor(cursor: CursorPosition, before: CursorPosition, after: CursorPosition, line_delta: c_long) -> CursorPosition {
+    if cursor.lnum == before.lnum && cursor.col >= before.col {
+        CursorPosition {
+            lnum: cursor.lnum + after.lnum - before.lnum,
+            col: cursor.col + after.col - before.col,
+            coladd: cursor.coladd
+        }
+    } else if cursor.lnum > before.lnum {
+        CursorPosition { lnum: cursor.lnum + line_delta, ..cursor }
+    } else {
+        cursor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(lnum: c_long, col: i32) -> CursorPosition {
+        CursorPosition { lnum, col, coladd: 0 }
+    }
+
+    #[test]
+    fn shift_cursor_test() {
+        // Typing a character moves a cursor further along the same line.
+        let shifted = shift_cursor(position(3, 10), position(3, 2), position(3, 3), 0);
+        assert_eq!((shifted.lnum, shifted.col), (3, 11));
+
+        // <CR> carries the rest of the line, and the cursor, to a new line.
+        let shifted = shift_cursor(position(3, 10), position(3, 2), position(4, 0), 1);
+        assert_eq!((shifted.lnum, shifted.col), (4, 8));
+
+        // Lines below only move down.
+        let shifted = shift_cursor(position(7, 1), position(3, 2), position(4, 0), 1);
+        assert_eq!((shifted.lnum, shifted.col), (8, 1));
+
+        // <BS> at the start of a line joins it with the previous one.
+        let shifted = shift_cursor(position(5, 3), position(5, 0), position(4, 6), -1);
+        assert_eq!((shifted.lnum, shifted.col), (4, 9));
+    }
+}
@@ -0,0 +1,301 @@
+use libc::{ c_int, c_long };
+use std::ffi::CString;
+use std::fmt;
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+use std::ptr;
+
+use crate::event::{ emit, VimEvent };
+use crate::{
+    char_u, eval_string, get_option_value, optionSet_T, set_option_value, string_from_vim,
+    vimSetOptionSetCallback, vim_free, OPT_GLOBAL, OPT_LOCAL, SOPT_BOOL, SOPT_NUM, SOPT_STRING
+};
+
+/// Which value of an option to change, like `:setglobal`, `:setlocal` and
+/// `:set`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OptionScope {
+    Global,
+    Local,
+    Both
+}
+
+impl OptionScope {
+    // Other bits such as OPT_MODELINE may be set along with the scope.
+    fn from_flags(flags: c_int) -> OptionScope {
+        let flags = flags as u32;
+        if flags & OPT_LOCAL != 0 {
+            OptionScope::Local
+        } else if flags & OPT_GLOBAL != 0 {
+            OptionScope::Global
+        } else {
+            OptionScope::Both
+        }
+    }
+
+    fn flags(self) -> c_int {
+        match self {
+            OptionScope::Global => OPT_GLOBAL as c_int,
+            OptionScope::Local => OPT_LOCAL as c_int,
+            OptionScope::Both => 0
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum OptionValue {
+    Bool(bool),
+    Number(i64),
+    String(String)
+}
+
+/// The Rust types options are read and set as. libvim reports boolean
+/// options as numbers, so `bool` and `i64` can each read both kinds.
+pub trait OptionType: Sized {
+    fn from_option(value: OptionValue) -> Option<Self>;
+    fn to_option(&self) -> OptionValue;
+}
+
+impl OptionType for bool {
+    fn from_option(value: OptionValue) -> Option<bool> {
+        match value {
+            OptionValue::Bool(value) => Some(value),
+            OptionValue::Number(value) => Some(value != 0),
+            OptionValue::String(_) => None
+        }
+    }
+
+    fn to_option(&self) -> OptionValue {
+        OptionValue::Bool(*self)
+    }
+}
+
+impl OptionType for i64 {
+    fn from_option(value: OptionValue) -> Option<i64> {
+        match value {
+            OptionValue::Bool(value) => Some(value as i64),
+            OptionValue::Number(value) => Some(value),
+            OptionValue::String(_) => None
+        }
+    }
+
+    fn to_option(&self) -> OptionValue {
+        OptionValue::Number(*self)
+    }
+}
+
+impl OptionType for String {
+    fn from_option(value: OptionValue) -> Option<String> {
+        match value {
+            OptionValue::String(value) => Some(value),
+            _ => None
+        }
+    }
+
+    fn to_option(&self) -> OptionValue {
+        OptionValue::String(self.clone())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum OptionError {
+    /// No such option, or one that is not compiled into libvim.
+    Unknown { name: String },
+    /// A string was used for a boolean or number option, a boolean for a
+    /// number option, or a number or boolean for a string option.
+    WrongType { name: String },
+    /// The option rejected the value.
+    Invalid { name: String, message: String }
+}
+
+impl fmt::Display for OptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptionError::Unknown { name } => write!(f, "Unknown option: {}", name),
+            OptionError::WrongType { name } => write!(f, "Wrong type of value for option: {}", name),
+            OptionError::Invalid { name, message } => write!(f, "Invalid value for option {}: {}", name, message)
+        }
+    }
+}
+
+impl std::error::Error for OptionError {}
+
+/// An option known to hold a `T`, so it can be used without naming the type.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VimOption<T> {
+    name: &'static str,
+    kind: PhantomData<T>
+}
+
+impl<T: OptionType> VimOption<T> {
+    pub const fn new(name: &'static str) -> VimOption<T> {
+        VimOption { name, kind: PhantomData }
+    }
+
+    pub fn name(self) -> &'static str {
+        self.name
+    }
+
+    pub fn get(self) -> Result<T, OptionError> {
+        vim_option(self.name)
+    }
+
+    pub fn set(self, value: T, scope: OptionScope) -> Result<(), OptionError> {
+        vim_set_option(self.name, value, scope)
+    }
+}
+
+impl VimOption<bool> {
+    pub const AUTOINDENT: VimOption<bool> = VimOption::new("autoindent");
+    pub const EXPANDTAB: VimOption<bool> = VimOption::new("expandtab");
+    pub const HLSEARCH: VimOption<bool> = VimOption::new("hlsearch");
+    pub const IGNORECASE: VimOption<bool> = VimOption::new("ignorecase");
+    pub const INCSEARCH: VimOption<bool> = VimOption::new("incsearch");
+    pub const LIST: VimOption<bool> = VimOption::new("list");
+    pub const MODIFIABLE: VimOption<bool> = VimOption::new("modifiable");
+    pub const NUMBER: VimOption<bool> = VimOption::new("number");
+    pub const READONLY: VimOption<bool> = VimOption::new("readonly");
+    pub const RELATIVENUMBER: VimOption<bool> = VimOption::new("relativenumber");
+    pub const SMARTCASE: VimOption<bool> = VimOption::new("smartcase");
+    pub const SMARTINDENT: VimOption<bool> = VimOption::new("smartindent");
+    pub const WRAP: VimOption<bool> = VimOption::new("wrap");
+    pub const WRAPSCAN: VimOption<bool> = VimOption::new("wrapscan");
+}
+
+impl VimOption<i64> {
+    pub const SCROLLOFF: VimOption<i64> = VimOption::new("scrolloff");
+    pub const SHIFTWIDTH: VimOption<i64> = VimOption::new("shiftwidth");
+    pub const SIDESCROLLOFF: VimOption<i64> = VimOption::new("sidescrolloff");
+    pub const SOFTTABSTOP: VimOption<i64> = VimOption::new("softtabstop");
+    pub const TABSTOP: VimOption<i64> = VimOption::new("tabstop");
+    pub const TEXTWIDTH: VimOption<i64> = VimOption::new("textwidth");
+    pub const UNDOLEVELS: VimOption<i64> = VimOption::new("undolevels");
+}
+
+impl VimOption<String> {
+    pub const BACKSPACE: VimOption<String> = VimOption::new("backspace");
+    pub const CLIPBOARD: VimOption<String> = VimOption::new("clipboard");
+    pub const COMMENTSTRING: VimOption<String> = VimOption::new("commentstring");
+    pub const FILEENCODING: VimOption<String> = VimOption::new("fileencoding");
+    pub const FILEFORMAT: VimOption<String> = VimOption::new("fileformat");
+    pub const FILETYPE: VimOption<String> = VimOption::new("filetype");
+}
+
+pub(crate) fn install_callbacks() {
+    unsafe { vimSetOptionSetCallback(Some(option_set)); }
+}
+
+// Hidden options are accepted by `:set` but do nothing, so they are not
+// reported.
+unsafe extern "C" fn option_set(option: *mut optionSet_T) {
+    if option.is_null() || (*option).hidden != 0 {
+        return;
+    }
+    let option = &*option;
+    let number: c_long = option.numval;
+    let value = match option.type_ as u32 {
+        SOPT_BOOL => OptionValue::Bool(number != 0),
+        SOPT_NUM => OptionValue::Number(number as i64),
+        SOPT_STRING => OptionValue::String(string_from_vim(option.stringval).unwrap_or_default()),
+        _ => match string_from_vim(option.stringval) {
+            Some(value) => OptionValue::String(value),
+            None => OptionValue::Number(number as i64)
+        }
+    };
+    let name = string_from_vim(option.fullname).unwrap_or_default();
+    emit(VimEvent::OptionChanged {
+        short_name: string_from_vim(option.shortname).unwrap_or_else(|| name.clone()),
+        name,
+        value,
+        scope: OptionScope::from_flags(option.opt_flags)
+    });
+}
+
+// Reads an option as libvim stores it: booleans come back as numbers.
+fn raw_option(name: &str) -> Result<OptionValue, OptionError> {
+    let unknown = || OptionError::Unknown { name: name.to_string() };
+    let c_name = CString::new(name).map_err(|_| unknown())?;
+    let mut number: c_long = 0;
+    let mut string: *mut char_u = ptr::null_mut();
+    unsafe {
+        match get_option_value(c_name.as_ptr() as *mut char_u, &mut number, &mut string, 0) {
+            1 => Ok(OptionValue::Number(number as i64)),
+            0 => {
+                let value = string_from_vim(string).unwrap_or_default();
+                vim_free(string as *mut c_void);
+                Ok(OptionValue::String(value))
+            },
+            // Hidden options exist by name but are not compiled in.
+            _ => Err(unknown())
+        }
+    }
+}
+
+/// Reads the value of the option `name` in effect for the current buffer
+/// and window, like `&name`. Short names such as `sw` work too.
+pub fn vim_option<T: OptionType>(name: &str) -> Result<T, OptionError> {
+    T::from_option(raw_option(name)?).ok_or_else(|| OptionError::WrongType { name: name.to_string() })
+}
+
+// libvim reads boolean options as numbers too, but `:set {name}?` shows a
+// boolean as `name` or `noname` and any other option as `name=value`.
+fn is_bool_option(name: &str) -> bool {
+    name.chars().all(|char| char.is_ascii_alphanumeric())
+        && eval_string(&format!("execute('set {}?')", name)).is_some_and(|shown| !shown.contains('='))
+}
+
+pub fn vim_set_option<T: OptionType>(name: &str, value: T, scope: OptionScope) -> Result<(), OptionError> {
+    let value = value.to_option();
+    let wrong_type = match (raw_option(name)?, &value) {
+        (OptionValue::String(_), value) => !matches!(value, OptionValue::String(_)),
+        (_, OptionValue::String(_)) => true,
+        (_, OptionValue::Bool(_)) => !is_bool_option(name),
+        _ => false
+    };
+    if wrong_type {
+        return Err(OptionError::WrongType { name: name.to_string() });
+    }
+    let (number, string) = match value {
+        OptionValue::Bool(value) => (value as c_long, None),
+        OptionValue::Number(value) => (value as c_long, None),
+        OptionValue::String(value) => (0, Some(CString::new(value).map_err(|_| OptionError::Invalid {
+            name: name.to_string(),
+            message: "Contains a NUL byte".to_string()
+        })?))
+    };
+    let c_name = CString::new(name).unwrap();
+    // The string is copied by libvim, and the message returned is static.
+    let message = unsafe {
+        let string = string.as_ref().map_or(ptr::null_mut(), |string| string.as_ptr() as *mut char_u);
+        set_option_value(c_name.as_ptr() as *mut char_u, number, string, scope.flags())
+    };
+    match unsafe { string_from_vim(message as *mut char_u) } {
+        Some(message) => Err(OptionError::Invalid { name: name.to_string(), message }),
+        None => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OPT_MODELINE;
+
+    #[test]
+    fn option_type_test() {
+        assert_eq!(bool::from_option(OptionValue::Number(1)), Some(true));
+        assert_eq!(i64::from_option(OptionValue::Bool(false)), Some(0));
+        assert_eq!(String::from_option(OptionValue::Number(8)), None);
+        assert_eq!(i64::from_option(OptionValue::String("Bender".to_string())), None);
+        assert_eq!(VimOption::SHIFTWIDTH.name(), "shiftwidth");
+        assert_eq!(VimOption::WRAP.name(), "wrap");
+        assert_eq!(OptionScope::from_flags(OptionScope::Local.flags()), OptionScope::Local);
+        assert_eq!(OptionScope::from_flags(OptionScope::Both.flags()), OptionScope::Both);
+    }
+
+    #[test]
+    fn option_scope_flags_test() {
+        assert_eq!(OptionScope::from_flags((OPT_LOCAL | OPT_MODELINE) as c_int), OptionScope::Local);
+        assert_eq!(OptionScope::from_flags((OPT_GLOBAL | OPT_MODELINE) as c_int), OptionScope::Global);
+        assert_eq!(OptionScope::from_flags(OPT_MODELINE as c_int), OptionScope::Both);
+    }
+}
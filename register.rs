@@ -0,0 +1,159 @@
+use libc::c_int;
+use std::os::raw::c_void;
+use std::ffi::CString;
+use std::ptr;
+
+use crate::{
+    char_u, eval_string, get_reg_contents, lines_from_vim, string_from_vim, vimRegisterGet,
+    vim_free, write_reg_contents_lst, GREG_EXPR_SRC, MBLOCK, MCHAR, MLINE
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RegisterKind {
+    Char,
+    Line,
+    Block
+}
+
+impl RegisterKind {
+    pub(crate) fn from_raw(raw: c_int) -> RegisterKind {
+        match raw as u32 {
+            MLINE => RegisterKind::Line,
+            MBLOCK => RegisterKind::Block,
+            _ => RegisterKind::Char
+        }
+    }
+
+    pub(crate) fn to_raw(self) -> c_int {
+        match self {
+            RegisterKind::Char => MCHAR as c_int,
+            RegisterKind::Line => MLINE as c_int,
+            RegisterKind::Block => MBLOCK as c_int
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Register {
+    pub lines: Vec<String>,
+    pub kind: RegisterKind
+}
+
+impl Register {
+    pub fn new(lines: Vec<String>, kind: RegisterKind) -> Register {
+        Register { lines, kind }
+    }
+
+    /// Builds a register from plain text, the way Vim does when pasting from
+    /// the system clipboard: a trailing newline makes it linewise.
+    pub fn from_text(text: &str) -> Register {
+        let normalized = text.replace("\r\n", "\n");
+        match normalized.strip_suffix('\n') {
+            Some(body) => Register::new(body.split('\n').map(String::from).collect(), RegisterKind::Line),
+            None => Register::new(normalized.split('\n').map(String::from).collect(), RegisterKind::Char)
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = self.lines.join("\n");
+        if self.kind == RegisterKind::Line {
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// Reads a register the way `getreg()` sees it. Covers the named, numbered,
+/// unnamed, small-delete and clipboard registers as well as the read-only
+/// ones (`/`, `:`, `.`, `%`, `#`). For `=` the last expression itself is
+/// returned rather than its value.
+pub fn vim_register_get(name: char) -> Option<Register> {
+    if !is_readable_register(name) {
+        return None;
+    }
+    let kind = register_kind(name)?;
+    let lines = if is_yank_register(name) {
+        unsafe {
+            let mut num_lines: c_int = 0;
+            let mut lines: *mut *mut char_u = ptr::null_mut();
+            vimRegisterGet(name as c_int, &mut num_lines, &mut lines);
+            lines_from_vim(lines, num_lines)
+        }
+    } else {
+        let flags = if name == '=' { GREG_EXPR_SRC as c_int } else { 0 };
+        unsafe {
+            let contents = get_reg_contents(name as c_int, flags);
+            let text = string_from_vim(contents)?;
+            vim_free(contents as *mut c_void);
+            let text = text.strip_suffix('\n').unwrap_or(&text);
+            text.split('\n').map(String::from).collect()
+        }
+    };
+    Some(Register::new(lines, kind))
+}
+
+/// Replaces the contents of a register. Uppercase names append, like `"Ay`.
+/// Returns `false` when the register cannot be written, including a
+/// multi-line value for `/` or `=`.
+pub fn vim_register_set(name: char, register: &Register) -> bool {
+    if !is_writable_register(name) {
+        return false;
+    }
+    if (name == '/' || name == '=') && register.lines.len() > 1 {
+        return false;
+    }
+    let lines: Vec<CString> = register.lines.iter()
+        .map(|line| CString::new(line.replace('\0', "\n")).unwrap())
+        .collect();
+    let mut strings: Vec<*mut char_u> = lines.iter().map(|line| line.as_ptr() as *mut char_u).collect();
+    strings.push(ptr::null_mut());
+    unsafe {
+        write_reg_contents_lst(
+            name as c_int,
+            strings.as_mut_ptr(),
+            -1,
+            name.is_ascii_uppercase() as c_int,
+            register.kind.to_raw(),
+            -1
+        );
+    }
+    true
+}
+
+pub(crate) fn is_yank_register(name: char) -> bool {
+    name.is_ascii_alphanumeric() || matches!(name, '"' | '-' | '+' | '*')
+}
+
+fn is_readable_register(name: char) -> bool {
+    is_yank_register(name) || matches!(name, '/' | ':' | '.' | '%' | '#' | '=')
+}
+
+fn is_writable_register(name: char) -> bool {
+    is_yank_register(name) || matches!(name, '/' | '=')
+}
+
+fn register_kind(name: char) -> Option<RegisterKind> {
+    let register_type = eval_string(&format!("getregtype('{}')", name))?;
+    match register_type.chars().next()? {
+        'V' => Some(RegisterKind::Line),
+        '\x16' => Some(RegisterKind::Block),
+        _ => Some(RegisterKind::Char)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_text_round_trip_test() {
+        let linewise = Register::from_text("fry\nleela\n");
+        assert_eq!(linewise.kind, RegisterKind::Line);
+        assert_eq!(linewise.lines, vec!["fry", "leela"]);
+        assert_eq!(linewise.to_text(), "fry\nleela\n");
+
+        let charwise = Register::from_text("bender");
+        assert_eq!(charwise.kind, RegisterKind::Char);
+        assert_eq!(charwise.to_text(), "bender");
+    }
+}
@@ -0,0 +1,292 @@
+use libc::{ c_int, c_long };
+
+use crate::event::HandlerSlot;
+use crate::{
+    buffer_line, colnr_T, linenr_T, screenLineMotion_T, screenLineMotion_T_MOTION_H,
+    screenLineMotion_T_MOTION_L, vimBufferGetCurrent, vimBufferGetLineCount,
+    vimOptionGetTabSize, vimSetCursorMoveScreenLineCallback,
+    vimSetCursorMoveScreenPositionCallback, vim_get_viewport, CursorPosition
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScreenLineMotion {
+    /// `H`
+    Top,
+    /// `M`
+    Middle,
+    /// `L`
+    Bottom
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScreenDirection {
+    /// `gk`
+    Up,
+    /// `gj`
+    Down
+}
+
+/// Answers the motions that depend on how lines are laid out on screen, which
+/// only the host knows once wrapping and folds come into play.
+pub trait ScreenModel {
+    /// Returns the line `H`, `M` or `L` moves to.
+    fn screen_line(&mut self, motion: ScreenLineMotion, count: c_int, start_line: c_long) -> Option<c_long>;
+
+    /// Returns the line and byte column `gj` or `gk` moves to. `curswant` is
+    /// the screen column the cursor would like to stay in.
+    fn screen_position(
+        &mut self,
+        direction: ScreenDirection,
+        count: c_int,
+        cursor: CursorPosition,
+        curswant: c_int
+    ) -> Option<(c_long, c_int)>;
+}
+
+/// Soft-wraps the current buffer at the viewport width, expanding tabs to
+/// `tabstop`. Folds are not taken into account.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SoftWrapScreen;
+
+impl SoftWrapScreen {
+    fn line(&self, line_number: c_long) -> Option<String> {
+        unsafe { buffer_line(vimBufferGetCurrent(), line_number) }
+    }
+
+    fn line_count(&self) -> c_long {
+        unsafe { vimBufferGetLineCount(vimBufferGetCurrent()) as c_long }
+    }
+}
+
+impl ScreenModel for SoftWrapScreen {
+    fn screen_line(&mut self, motion: ScreenLineMotion, count: c_int, _start_line: c_long) -> Option<c_long> {
+        let viewport = vim_get_viewport();
+        let tabstop = unsafe { vimOptionGetTabSize() };
+        let top_line = (viewport.top_line as c_long).max(1);
+        let mut visible: Vec<(c_long, c_int)> = Vec::new();
+        let mut rows = 0;
+        for line_number in top_line..=self.line_count() {
+            let line_rows = screen_rows(&self.line(line_number)?, viewport.width, tabstop);
+            if rows + line_rows > viewport.height && !visible.is_empty() {
+                break;
+            }
+            visible.push((line_number, rows));
+            rows += line_rows;
+        }
+        if visible.is_empty() {
+            return None;
+        }
+        let offset = (count.max(1) - 1) as usize;
+        let index = match motion {
+            ScreenLineMotion::Top => offset.min(visible.len() - 1),
+            ScreenLineMotion::Bottom => visible.len().saturating_sub(offset + 1),
+            ScreenLineMotion::Middle => {
+                let middle_row = (rows - 1) / 2;
+                visible.iter().rposition(|(_, row)| *row <= middle_row).unwrap_or(0)
+            }
+        };
+        visible.get(index).map(|(line_number, _)| *line_number)
+    }
+
+    fn screen_position(
+        &mut self,
+        direction: ScreenDirection,
+        count: c_int,
+        cursor: CursorPosition,
+        curswant: c_int
+    ) -> Option<(c_long, c_int)> {
+        let width = vim_get_viewport().width.max(1);
+        let tabstop = unsafe { vimOptionGetTabSize() };
+        let last_line = self.line_count();
+        let mut line_number = cursor.lnum;
+        let mut line = self.line(line_number)?;
+        let mut row = display_width(&line[..char_boundary(&line, cursor.col)], tabstop) / width;
+        let want = curswant.max(0) % width;
+        for _ in 0..count.max(1) {
+            match direction {
+                ScreenDirection::Down if row + 1 < screen_rows(&line, width, tabstop) => row += 1,
+                ScreenDirection::Down if line_number < last_line => {
+                    line_number += 1;
+                    line = self.line(line_number)?;
+                    row = 0;
+                },
+                ScreenDirection::Up if row > 0 => row -= 1,
+                ScreenDirection::Up if line_number > 1 => {
+                    line_number -= 1;
+                    line = self.line(line_number)?;
+                    row = screen_rows(&line, width, tabstop) - 1;
+                },
+                _ => break
+            }
+        }
+        Some((line_number, column_at(&line, row * width + want, tabstop)))
+    }
+}
+
+/// Screen cells taken by `text`, with tabs expanded to `tabstop` and wide
+/// characters taking two cells.
+pub fn display_width(text: &str, tabstop: c_int) -> c_int {
+    text.chars().fold(0, |width, c| width + char_width(c, width, tabstop))
+}
+
+/// How many screen rows `line` takes up when wrapped at `width` cells.
+pub fn screen_rows(line: &str, width: c_int, tabstop: c_int) -> c_int {
+    let line_width = display_width(line, tabstop);
+    if width <= 0 || line_width == 0 {
+        1
+    } else {
+        (line_width + width - 1) / width
+    }
+}
+
+// East Asian wide and fullwidth characters, and the emoji blocks, which Vim
+// draws in two cells.
+const WIDE: [(u32, u32); 17] = [
+    (0x1100, 0x115f),
+    (0x2e80, 0x303e),
+    (0x3041, 0x33ff),
+    (0x3400, 0x4dbf),
+    (0x4e00, 0x9fff),
+    (0xa000, 0xa4cf),
+    (0xa960, 0xa97f),
+    (0xac00, 0xd7a3),
+    (0xf900, 0xfaff),
+    (0xfe10, 0xfe19),
+    (0xfe30, 0xfe6f),
+    (0xff00, 0xff60),
+    (0xffe0, 0xffe6),
+    (0x1f300, 0x1f64f),
+    (0x1f900, 0x1f9ff),
+    (0x20000, 0x2fffd),
+    (0x30000, 0x3fffd)
+];
+
+// Combining marks, zero width spaces and variation selectors.
+const ZERO_WIDTH: [(u32, u32); 5] = [
+    (0x0300, 0x036f),
+    (0x1ab0, 0x1aff),
+    (0x20d0, 0x20ff),
+    (0x200b, 0x200f),
+    (0xfe00, 0xfe0f)
+];
+
+fn char_width(c: char, column: c_int, tabstop: c_int) -> c_int {
+    let in_table = |table: &[(u32, u32)]| table.iter().any(|(first, last)| (*first..=*last).contains(&(c as u32)));
+    if c == '\t' {
+        let tabstop = tabstop.max(1);
+        tabstop - column % tabstop
+    } else if c.is_control() {
+        // Shown as `^X`.
+        2
+    } else if in_table(&ZERO_WIDTH) {
+        0
+    } else if in_table(&WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+// `col` moved back to the start of the character it falls in.
+fn char_boundary(line: &str, col: c_int) -> usize {
+    let mut col = (col.max(0) as usize).min(line.len());
+    while !line.is_char_boundary(col) {
+        col -= 1;
+    }
+    col
+}
+
+// The byte column of the character covering screen column `vcol`, or of the
+// last character when the line is shorter.
+fn column_at(line: &str, vcol: c_int, tabstop: c_int) -> c_int {
+    let mut width = 0;
+    let mut last = 0;
+    for (index, c) in line.char_indices() {
+        last = index;
+        width += char_width(c, width, tabstop);
+        if width > vcol {
+            return index as c_int;
+        }
+    }
+    last as c_int
+}
+
+thread_local! {
+    static MODEL: HandlerSlot<dyn ScreenModel> = const { HandlerSlot::new() };
+}
+
+pub fn vim_set_screen_model<M: ScreenModel + 'static>(model: M) {
+    MODEL.with(|slot| slot.set(Some(Box::new(model))));
+    unsafe {
+        vimSetCursorMoveScreenLineCallback(Some(cursor_move_screen_line));
+        vimSetCursorMoveScreenPositionCallback(Some(cursor_move_screen_position));
+    }
+}
+
+pub fn vim_clear_screen_model() {
+    MODEL.with(|slot| slot.set(None));
+    unsafe {
+        vimSetCursorMoveScreenLineCallback(None);
+        vimSetCursorMoveScreenPositionCallback(None);
+    }
+}
+
+unsafe extern "C" fn cursor_move_screen_line(
+    motion: screenLineMotion_T,
+    count: c_int,
+    start_line: linenr_T,
+    dest_line: *mut linenr_T
+) {
+    let motion = if motion == screenLineMotion_T_MOTION_H {
+        ScreenLineMotion::Top
+    } else if motion == screenLineMotion_T_MOTION_L {
+        ScreenLineMotion::Bottom
+    } else {
+        ScreenLineMotion::Middle
+    };
+    let line = MODEL.with(|slot| slot.call(|model| model.screen_line(motion, count, start_line)));
+    *dest_line = line.flatten().unwrap_or(start_line);
+}
+
+unsafe extern "C" fn cursor_move_screen_position(
+    direction: c_int,
+    count: c_int,
+    lnum: linenr_T,
+    cursor: colnr_T,
+    curswant: colnr_T,
+    dest_lnum: *mut linenr_T,
+    dest_col: *mut colnr_T
+) {
+    let direction = if direction < 0 { ScreenDirection::Up } else { ScreenDirection::Down };
+    let position = CursorPosition { lnum, col: cursor, coladd: 0 };
+    let destination = MODEL.with(|slot| {
+        slot.call(|model| model.screen_position(direction, count, position, curswant))
+    });
+    let (line, column) = destination.flatten().unwrap_or((lnum, cursor));
+    *dest_lnum = line;
+    *dest_col = column;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soft_wrap_test() {
+        assert_eq!(display_width("\tx", 8), 9);
+        assert_eq!(display_width("ab\tx", 4), 5);
+        assert_eq!(screen_rows("", 10, 8), 1);
+        assert_eq!(screen_rows("0123456789", 10, 8), 1);
+        assert_eq!(screen_rows("0123456789a", 10, 8), 2);
+        assert_eq!(column_at("\tabc", 3, 4), 0);
+        assert_eq!(column_at("\tabc", 5, 4), 2);
+        assert_eq!(column_at("abc", 20, 4), 2);
+
+        assert_eq!(display_width("日本語", 8), 6);
+        assert_eq!(display_width("e\u{301}", 8), 1);
+        assert_eq!(screen_rows("日本語日本語", 10, 8), 2);
+        assert_eq!(column_at("日本語", 3, 8), 3);
+        assert_eq!(char_boundary("日本語", 4), 3);
+        assert_eq!(char_boundary("abc", 10), 3);
+    }
+}
@@ -0,0 +1,157 @@
+use libc::c_long;
+use std::fmt;
+use std::io::{ self, Write };
+use std::process::{ Command, ExitStatus, Stdio };
+use std::thread;
+
+use crate::event::{ emit, VimEvent };
+use crate::{
+    buffer_line, char_u, file_buffer, string_from_vim, vimSetOutputCallback, vim_buffer_line_count,
+    vim_buffer_set_lines, vim_option
+};
+
+pub(crate) fn install_callbacks() {
+    unsafe { vimSetOutputCallback(Some(output)); }
+}
+
+unsafe extern "C" fn output(cmd: *mut char_u, output: *mut char_u, is_silent: libc::c_int) {
+    emit(VimEvent::ShellOutput {
+        command: string_from_vim(cmd).unwrap_or_default(),
+        output: string_from_vim(output).unwrap_or_default(),
+        silent: is_silent != 0
+    });
+}
+
+#[derive(Debug)]
+pub enum ShellError {
+    /// The command could not be started or fed its input.
+    Io(io::Error),
+    /// The lines to filter are not in the buffer.
+    InvalidRange { start: c_long, end: c_long },
+    /// The command exited unsuccessfully; the buffer was left untouched.
+    Failed { status: ExitStatus, stderr: String }
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShellError::Io(error) => write!(f, "Failed to run shell command: {}", error),
+            ShellError::InvalidRange { start, end } => write!(f, "Invalid range: {},{}", start, end),
+            ShellError::Failed { status, stderr } => write!(f, "Shell command failed ({}): {}", status, stderr.trim_end())
+        }
+    }
+}
+
+impl std::error::Error for ShellError {}
+
+impl From<io::Error> for ShellError {
+    fn from(error: io::Error) -> ShellError {
+        ShellError::Io(error)
+    }
+}
+
+/// The output of a successful `vim_shell_filter`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ShellFilterOutput {
+    /// The lines that replaced the filtered ones.
+    pub lines: Vec<String>,
+    pub status: ExitStatus
+}
+
+/// Pipes lines `start` to `end` through `command` using `'shell'` and
+/// `'shellcmdflag'`, like `:{range}!command`, and replaces them with its
+/// output. An `end` of -1 means the last line.
+pub fn vim_shell_filter(buffer: &mut file_buffer, start: c_long, end: c_long, command: &str) -> Result<ShellFilterOutput, ShellError> {
+    let line_count = vim_buffer_line_count(buffer) as c_long;
+    let end = if end == -1 { line_count } else { end };
+    if start < 1 || end < start || end > line_count {
+        return Err(ShellError::InvalidRange { start, end });
+    }
+    let input: Vec<String> = (start..=end)
+        .map(|line| unsafe { buffer_line(buffer, line) }.unwrap_or_default())
+        .collect();
+    let (shell, flag) = shell_options();
+    let output = shell_filter(&shell, &flag, command, &input)?;
+    vim_buffer_set_lines(buffer, start - 1, end, &output.lines);
+    Ok(output)
+}
+
+// `'shell'` and `'shellcmdflag'`, falling back to `/bin/sh -c`.
+pub(crate) fn shell_options() -> (String, String) {
+    let shell = vim_option::<String>("shell").ok().filter(|shell| !shell.trim().is_empty());
+    let flag = vim_option::<String>("shellcmdflag").unwrap_or_else(|_| "-c".to_string());
+    (shell.unwrap_or_else(|| "/bin/sh".to_string()), flag)
+}
+
+// Like Vim, splits `'shell'` and `'shellcmdflag'` at white space. Without a
+// command the shell is started on its own.
+pub(crate) fn shell_command(shell: &str, flag: &str, command: Option<&str>) -> Command {
+    let mut shell = shell.split_whitespace();
+    let mut shell_command = Command::new(shell.next().unwrap_or("/bin/sh"));
+    shell_command.args(shell);
+    if let Some(command) = command {
+        shell_command.args(flag.split_whitespace()).arg(command);
+    }
+    shell_command
+}
+
+fn shell_filter(shell: &str, flag: &str, command: &str, input: &[String]) -> Result<ShellFilterOutput, ShellError> {
+    let mut child = shell_command(shell, flag, Some(command))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Written from another thread so a command that produces output before
+    // reading all of its input cannot deadlock against us.
+    let mut stdin = child.stdin.take().expect("Piped stdin");
+    let text: String = input.iter().map(|line| format!("{}\n", line)).collect();
+    let writer = thread::spawn(move || stdin.write_all(text.as_bytes()));
+    let output = child.wait_with_output()?;
+    match writer.join() {
+        // Commands are free to exit without reading everything.
+        Ok(Err(error)) if error.kind() != io::ErrorKind::BrokenPipe => return Err(error.into()),
+        _ => {}
+    }
+
+    if !output.status.success() {
+        return Err(ShellError::Failed {
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned()
+        });
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines = if stdout.is_empty() {
+        Vec::new()
+    } else {
+        stdout.strip_suffix('\n').unwrap_or(&stdout).split('\n').map(String::from).collect()
+    };
+    Ok(ShellFilterOutput { lines, status: output.status })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn shell_filter_test() {
+        let sorted = shell_filter("/bin/sh", "-c", "sort", &lines(&["leela", "fry", "bender"])).expect("Run sort");
+        assert_eq!(sorted.lines, lines(&["bender", "fry", "leela"]));
+        assert!(sorted.status.success());
+
+        let quiet = shell_filter("/bin/sh -e", "-c", "true", &lines(&["zoidberg"])).expect("Run true");
+        assert!(quiet.lines.is_empty());
+
+        match shell_filter("/bin/sh", "-c", "echo oops >&2; exit 3", &lines(&["fry"])) {
+            Err(ShellError::Failed { status, stderr }) => {
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(stderr, "oops\n");
+            },
+            result => panic!("Expected a failure, got {:?}", result)
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use libc::c_int;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::event::{ follow_events, vim_remove_event_listener, EventListenerId, HandlerSlot, VimEvent };
+use crate::{
+    tabPageKind_T_CLOSE, tabPageKind_T_GOTO, tabPageKind_T_MOVE, tabPageKind_T_ONLY,
+    tabPageRequest_T, vimBufferNew, vimBufferSetCurrent, vimCursorSetPosition,
+    vimSetTabPageCallback, vim_buffer_open, CursorPosition, Layout, SplitKind
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TabPageKind {
+    /// `gt`, `gT`, `:tabnext`, `:tabprevious`
+    Goto,
+    /// `:tabmove`
+    Move,
+    /// `:tabclose`
+    Close,
+    /// `:tabonly`
+    Only
+}
+
+/// Tab pages are numbered from 1, as in Vim. For `Goto`, `Close` and `Only`,
+/// `Absolute(0)` stands for the current tab page, or for `gt` without a
+/// count, the next one. For `Move`, `Absolute(n)` puts the current tab page
+/// after the `n`th of the others, so 0 makes it the first, as `:tabmove` does.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TabTarget {
+    Absolute(c_int),
+    Relative(c_int)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TabPageRequest {
+    pub kind: TabPageKind,
+    pub target: TabTarget
+}
+
+impl TabPageRequest {
+    fn from_raw(raw: &tabPageRequest_T) -> Option<TabPageRequest> {
+        let kinds = [
+            (tabPageKind_T_GOTO, TabPageKind::Goto),
+            (tabPageKind_T_MOVE, TabPageKind::Move),
+            (tabPageKind_T_CLOSE, TabPageKind::Close),
+            (tabPageKind_T_ONLY, TabPageKind::Only)
+        ];
+        let kind = kinds.iter().find(|(raw_kind, _)| *raw_kind == raw.kind).map(|(_, kind)| *kind)?;
+        let target = if raw.relative != 0 {
+            TabTarget::Relative(raw.arg)
+        } else {
+            TabTarget::Absolute(raw.arg)
+        };
+        Some(TabPageRequest { kind, target })
+    }
+}
+
+thread_local! {
+    static HANDLER: HandlerSlot<dyn FnMut(&TabPageRequest) -> bool> = const { HandlerSlot::new() };
+}
+
+/// The handler returns `true` to accept the request; rejected requests show
+/// up as a failed command in Vim.
+pub fn vim_set_tab_page_handler<F: FnMut(&TabPageRequest) -> bool + 'static>(handler: F) {
+    HANDLER.with(|slot| slot.set(Some(Box::new(handler))));
+    unsafe { vimSetTabPageCallback(Some(tab_page_request)); }
+}
+
+pub fn vim_clear_tab_page_handler() {
+    HANDLER.with(|slot| slot.set(None));
+    unsafe { vimSetTabPageCallback(None); }
+}
+
+unsafe extern "C" fn tab_page_request(tab_page_info: tabPageRequest_T) -> c_int {
+    let request = match TabPageRequest::from_raw(&tab_page_info) {
+        Some(request) => request,
+        None => return 0
+    };
+    HANDLER.with(|slot| slot.call(|handler| handler(&request))).unwrap_or(false) as c_int
+}
+
+pub type TabId = usize;
+
+#[derive(Debug)]
+pub struct Tab {
+    pub id: TabId,
+    pub layout: Layout
+}
+
+/// A list of tab pages, each with its own window layout. Tab splits
+/// (`:tabnew`, `:tabedit`) add tab pages, tab page requests switch, move and
+/// close them, and window events go to the current tab's layout.
+#[derive(Debug)]
+pub struct TabList {
+    tabs: Vec<Tab>,
+    current: usize,
+    next_id: TabId,
+    listener: Option<EventListenerId>
+}
+
+impl TabList {
+    pub fn new() -> TabList {
+        TabList { tabs: vec![Tab { id: 0, layout: Layout::new() }], current: 0, next_id: 1, listener: None }
+    }
+
+    /// Creates a tab list that follows libvim's events and answers its tab
+    /// page requests, replacing any handler set with `vim_set_tab_page_handler`.
+    /// Requests are rejected while the host has the tab list borrowed, and
+    /// once it is dropped or detached.
+    pub fn attach() -> Rc<RefCell<TabList>> {
+        let tabs = Rc::new(RefCell::new(TabList::new()));
+        let listener = follow_events(&tabs, TabList::handle_event);
+        tabs.borrow_mut().listener = Some(listener);
+        let handler_tabs = Rc::downgrade(&tabs);
+        vim_set_tab_page_handler(move |request| {
+            let tabs = match handler_tabs.upgrade() {
+                Some(tabs) => tabs,
+                None => return false
+            };
+            let accepted = tabs.try_borrow_mut().is_ok_and(|mut tabs| tabs.listener.is_some() && tabs.apply(request));
+            accepted
+        });
+        tabs
+    }
+
+    /// Stops following libvim's events and answering its requests.
+    pub fn detach(&mut self) {
+        if let Some(listener) = self.listener.take() {
+            vim_remove_event_listener(listener);
+        }
+    }
+
+    pub fn tabs(&self) -> &[Tab] {
+        &self.tabs
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    pub fn current(&self) -> &Tab {
+        &self.tabs[self.current]
+    }
+
+    pub fn current_mut(&mut self) -> &mut Tab {
+        &mut self.tabs[self.current]
+    }
+
+    pub fn handle_event(&mut self, event: &VimEvent) {
+        match event {
+            VimEvent::WindowSplit { kind: kind @ (SplitKind::Tab | SplitKind::TabNew), filename } => {
+                self.open(*kind == SplitKind::TabNew, filename.as_deref());
+            },
+            event => self.current_mut().layout.handle_event(event)
+        }
+    }
+
+    /// Adds a tab page after the current one and switches to it. It shows
+    /// `filename`, a new empty buffer, or the current buffer.
+    pub fn open(&mut self, new_buffer: bool, filename: Option<&str>) {
+        self.current_mut().layout.save_state();
+        unsafe {
+            match filename {
+                Some(filename) => {
+                    vim_buffer_open(filename);
+                },
+                None if new_buffer => {
+                    let buffer = vimBufferNew(0);
+                    if !buffer.is_null() {
+                        vimBufferSetCurrent(buffer);
+                    }
+                },
+                None => {}
+            }
+            if filename.is_some() || new_buffer {
+                vimCursorSetPosition(CursorPosition { lnum: 1, col: 0, coladd: 0 });
+            }
+        }
+        let tab = Tab { id: self.next_id, layout: Layout::new() };
+        self.next_id += 1;
+        self.current += 1;
+        self.tabs.insert(self.current, tab);
+    }
+
+    /// Applies a tab page request, returning `false` when it cannot be
+    /// carried out, like closing the last tab page.
+    pub fn apply(&mut self, request: &TabPageRequest) -> bool {
+        let count = self.tabs.len();
+        match request.kind {
+            TabPageKind::Goto => {
+                let index = match goto_index(self.current, count, request.target) {
+                    Some(index) => index,
+                    None => return false
+                };
+                self.switch_to(index);
+                true
+            },
+            TabPageKind::Move => {
+                let tab = self.tabs.remove(self.current);
+                self.current = move_index(self.current, count, request.target);
+                self.tabs.insert(self.current, tab);
+                true
+            },
+            TabPageKind::Close => {
+                let index = match target_index(self.current, count, request.target) {
+                    Some(index) if count > 1 => index,
+                    _ => return false
+                };
+                if index == self.current {
+                    let next = if index + 1 < self.tabs.len() { index + 1 } else { index - 1 };
+                    self.switch_to(next);
+                }
+                self.tabs.remove(index);
+                if index < self.current {
+                    self.current -= 1;
+                }
+                true
+            },
+            TabPageKind::Only => {
+                let index = match target_index(self.current, count, request.target) {
+                    Some(index) => index,
+                    None => return false
+                };
+                self.switch_to(index);
+                let tab = self.tabs.remove(index);
+                self.tabs = vec![tab];
+                self.current = 0;
+                true
+            }
+        }
+    }
+
+    fn switch_to(&mut self, index: usize) {
+        if index == self.current {
+            return;
+        }
+        self.current_mut().layout.save_state();
+        self.current = index;
+        self.current_mut().layout.restore_state();
+    }
+}
+
+impl Default for TabList {
+    fn default() -> TabList {
+        TabList::new()
+    }
+}
+
+impl Drop for TabList {
+    fn drop(&mut self) {
+        self.detach();
+    }
+}
+
+// The tab page `gt` and `:tabnext` go to; `gt` and relative moves wrap
+// around.
+fn goto_index(current: usize, count: usize, target: TabTarget) -> Option<usize> {
+    let (current, count) = (current as c_int, count as c_int);
+    let index = match target {
+        TabTarget::Absolute(0) => (current + 1).rem_euclid(count),
+        TabTarget::Absolute(number) if (1..=count).contains(&number) => number - 1,
+        TabTarget::Absolute(_) => return None,
+        TabTarget::Relative(offset) => (current + offset).rem_euclid(count)
+    };
+    Some(index as usize)
+}
+
+// Where `:tabmove` puts the current tab page, counted once it has been taken
+// out of the list.
+fn move_index(current: usize, count: usize, target: TabTarget) -> usize {
+    let last = count as c_int - 1;
+    let index = match target {
+        TabTarget::Absolute(position) => position.clamp(0, last),
+        TabTarget::Relative(offset) => (current as c_int + offset).clamp(0, last)
+    };
+    index as usize
+}
+
+// The tab page `:tabclose` and `:tabonly` act on.
+fn target_index(current: usize, count: usize, target: TabTarget) -> Option<usize> {
+    let index = match target {
+        TabTarget::Absolute(0) => current as c_int,
+        TabTarget::Absolute(number) => number - 1,
+        TabTarget::Relative(offset) => current as c_int + offset
+    };
+    if index >= 0 && (index as usize) < count { Some(index as usize) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tab_index_test() {
+        assert_eq!(goto_index(0, 3, TabTarget::Absolute(0)), Some(1));
+        assert_eq!(goto_index(2, 3, TabTarget::Absolute(0)), Some(0));
+        assert_eq!(goto_index(0, 3, TabTarget::Absolute(3)), Some(2));
+        assert_eq!(goto_index(0, 3, TabTarget::Absolute(4)), None);
+        assert_eq!(goto_index(0, 3, TabTarget::Absolute(-1)), None);
+        assert_eq!(goto_index(0, 3, TabTarget::Relative(-1)), Some(2));
+
+        assert_eq!(move_index(1, 3, TabTarget::Absolute(0)), 0);
+        assert_eq!(move_index(0, 3, TabTarget::Absolute(1)), 1);
+        assert_eq!(move_index(0, 3, TabTarget::Absolute(99)), 2);
+        assert_eq!(move_index(1, 3, TabTarget::Relative(1)), 2);
+        assert_eq!(move_index(1, 3, TabTarget::Relative(-5)), 0);
+
+        assert_eq!(target_index(1, 3, TabTarget::Absolute(0)), Some(1));
+        assert_eq!(target_index(1, 3, TabTarget::Absolute(3)), Some(2));
+        assert_eq!(target_index(1, 3, TabTarget::Relative(-1)), Some(0));
+        assert_eq!(target_index(1, 3, TabTarget::Absolute(4)), None);
+    }
+}
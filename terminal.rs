@@ -0,0 +1,517 @@
+use libc::c_int;
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::fs::{ File, OpenOptions };
+use std::io::{ self, Read, Write };
+use std::os::unix::io::{ AsRawFd, FromRawFd };
+use std::os::unix::process::CommandExt;
+use std::process::{ Child, ExitStatus, Stdio };
+use std::rc::Rc;
+use std::sync::mpsc::{ self, Receiver };
+use std::thread;
+
+use crate::event::{ emit, follow_events, vim_remove_event_listener, EventListenerId, VimEvent };
+use crate::shell::{ shell_command, shell_options };
+use crate::{
+    string_from_vim, terminalRequest_t, vimBufferGetById, vimBufferGetCurrent, vimBufferGetId,
+    vimBufferGetLineCount, vimBufferNew, vimBufferSetCurrent, vimCursorSetPosition,
+    vimSetTerminalCallback, vim_buffer_set_lines, vim_execute, vim_get_viewport, CursorPosition, Key,
+    KeyCode
+};
+
+/// What happens to a terminal buffer once its job exits, set with `++close`,
+/// `++open` and `++noclose`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TerminalFinish {
+    Default,
+    Close,
+    Open,
+    NoClose
+}
+
+impl TerminalFinish {
+    fn from_raw(raw: u8) -> TerminalFinish {
+        match raw {
+            b'c' => TerminalFinish::Close,
+            b'o' => TerminalFinish::Open,
+            b'n' => TerminalFinish::NoClose,
+            _ => TerminalFinish::Default
+        }
+    }
+}
+
+/// A `:terminal` command. `command` is `None` when the shell should be
+/// started, and `rows` or `cols` are 0 unless given with `++rows`/`++cols`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TerminalRequest {
+    pub command: Option<String>,
+    pub rows: c_int,
+    pub cols: c_int,
+    pub current_window: bool,
+    pub finish: TerminalFinish,
+    pub hidden: bool
+}
+
+pub(crate) fn install_callbacks() {
+    unsafe { vimSetTerminalCallback(Some(terminal_requested)); }
+}
+
+unsafe extern "C" fn terminal_requested(request: *mut terminalRequest_t) {
+    if request.is_null() {
+        return;
+    }
+    let request = &*request;
+    emit(VimEvent::TerminalRequested(TerminalRequest {
+        command: string_from_vim(request.cmd).filter(|command| !command.is_empty()),
+        rows: request.rows,
+        cols: request.cols,
+        current_window: request.curwin != 0,
+        finish: TerminalFinish::from_raw(request.finish as u8),
+        hidden: request.hidden != 0
+    }));
+}
+
+/// A job running on a pseudo terminal, with its output streamed into a
+/// buffer of its own. Escape sequences are dropped, so the buffer holds the
+/// plain text a dumb terminal would show.
+pub struct PtyTerminal {
+    buffer_id: c_int,
+    master: File,
+    child: Child,
+    output: Receiver<Vec<u8>>,
+    screen: TerminalOutput,
+    finish: TerminalFinish,
+    exit_status: Option<ExitStatus>
+}
+
+impl PtyTerminal {
+    /// Starts the job for `request` in a new buffer, running its command
+    /// through `'shell'` like `:!` does, or the shell itself when there is
+    /// none. Unless the request is hidden the buffer becomes the current one.
+    pub fn open(request: &TerminalRequest) -> io::Result<PtyTerminal> {
+        let viewport = vim_get_viewport();
+        let rows = if request.rows > 0 { request.rows } else { viewport.height.max(1) };
+        let cols = if request.cols > 0 { request.cols } else { viewport.width.max(1) };
+        let (master, slave) = open_pty(rows, cols)?;
+
+        let (shell, flag) = shell_options();
+        let mut command = shell_command(&shell, &flag, request.command.as_deref());
+        command.env("TERM", "dumb")
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let mut reader = master.try_clone()?;
+        let mut child = command.spawn()?;
+        drop(command);
+
+        let buffer = unsafe { vimBufferNew(0) };
+        if buffer.is_null() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::other("Failed to create a terminal buffer"));
+        }
+        if !request.hidden {
+            unsafe { vimBufferSetCurrent(buffer); }
+        }
+
+        let (sender, output) = mpsc::channel();
+        thread::spawn(move || {
+            let mut chunk = [0; 4096];
+            // Reading fails with EIO once the job and its children are gone.
+            while let Ok(count) = reader.read(&mut chunk) {
+                if count == 0 || sender.send(chunk[..count].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(PtyTerminal {
+            buffer_id: unsafe { vimBufferGetId(buffer) },
+            master,
+            child,
+            output,
+            screen: TerminalOutput::default(),
+            finish: request.finish,
+            exit_status: None
+        })
+    }
+
+    pub fn buffer_id(&self) -> c_int {
+        self.buffer_id
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.exit_status.is_none()
+    }
+
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.exit_status
+    }
+
+    pub fn send_text(&mut self, text: &str) -> io::Result<()> {
+        self.master.write_all(text.as_bytes())
+    }
+
+    /// Sends a key the way a terminal encodes it. Keys a dumb terminal has
+    /// no encoding for are dropped.
+    pub fn send_key(&mut self, key: Key) -> io::Result<()> {
+        self.master.write_all(&key_bytes(key))
+    }
+
+    pub fn resize(&mut self, rows: c_int, cols: c_int) -> io::Result<()> {
+        set_window_size(self.master.as_raw_fd(), rows, cols)
+    }
+
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.child.kill()
+    }
+
+    /// Copies the output received so far into the buffer. Returns false once
+    /// the job has exited.
+    pub fn poll(&mut self) -> bool {
+        if self.exit_status.is_some() {
+            return false;
+        }
+        // Checked before reading, so output written just before the job
+        // exited is shown. A background job that keeps the terminal open
+        // does not hold up the exit.
+        let status = self.child.try_wait().ok().flatten();
+        let mut lines = Vec::new();
+        let mut received = false;
+        while let Ok(bytes) = self.output.try_recv() {
+            lines.extend(self.screen.feed(&bytes));
+            received = true;
+        }
+        if received {
+            lines.push(self.screen.current().to_string());
+            self.write_lines(&lines);
+        }
+
+        if let Some(status) = status {
+            self.exit_status = Some(status);
+            self.finished();
+        }
+        self.exit_status.is_none()
+    }
+
+    // The last buffer line always holds the line the job is still writing.
+    fn write_lines(&mut self, lines: &[String]) {
+        unsafe {
+            let buffer = vimBufferGetById(self.buffer_id);
+            if buffer.is_null() {
+                return;
+            }
+            let line_count = vimBufferGetLineCount(buffer) as libc::c_long;
+            vim_buffer_set_lines(&mut *buffer, line_count - 1, line_count, lines);
+            if vimBufferGetCurrent() == buffer {
+                let lnum = vimBufferGetLineCount(buffer) as libc::c_long;
+                vimCursorSetPosition(CursorPosition { lnum, col: 0, coladd: 0 });
+            }
+        }
+    }
+
+    fn finished(&mut self) {
+        let buffer = unsafe { vimBufferGetById(self.buffer_id) };
+        if buffer.is_null() {
+            return;
+        }
+        match self.finish {
+            TerminalFinish::Close => vim_execute(&format!("bwipeout! {}", self.buffer_id)),
+            TerminalFinish::Open => unsafe { vimBufferSetCurrent(buffer) },
+            TerminalFinish::Default | TerminalFinish::NoClose => {}
+        }
+    }
+}
+
+impl Drop for PtyTerminal {
+    fn drop(&mut self) {
+        if self.exit_status.is_none() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Services `:terminal` with a `PtyTerminal` per request. The host calls
+/// `poll` from its event loop and routes keys through `key` while a
+/// terminal buffer is current.
+#[derive(Default)]
+pub struct PtyTerminals {
+    terminals: Vec<PtyTerminal>,
+    error: Option<io::Error>,
+    listener: Option<EventListenerId>
+}
+
+impl PtyTerminals {
+    pub fn new() -> PtyTerminals {
+        PtyTerminals::default()
+    }
+
+    /// Creates terminals that open for every `:terminal` until dropped or
+    /// detached.
+    pub fn attach() -> Rc<RefCell<PtyTerminals>> {
+        let terminals = Rc::new(RefCell::new(PtyTerminals::new()));
+        let listener = follow_events(&terminals, |terminals, event| {
+            if let VimEvent::TerminalRequested(request) = event {
+                terminals.open(request);
+            }
+        });
+        terminals.borrow_mut().listener = Some(listener);
+        terminals
+    }
+
+    /// Stops opening terminals for `:terminal`.
+    pub fn detach(&mut self) {
+        if let Some(listener) = self.listener.take() {
+            vim_remove_event_listener(listener);
+        }
+    }
+
+    pub fn open(&mut self, request: &TerminalRequest) {
+        match PtyTerminal::open(request) {
+            Ok(terminal) => self.terminals.push(terminal),
+            Err(error) => self.error = Some(error)
+        }
+    }
+
+    /// The error from the last request that failed to start, if any.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub fn terminals(&self) -> &[PtyTerminal] {
+        &self.terminals
+    }
+
+    pub fn get_mut(&mut self, buffer_id: c_int) -> Option<&mut PtyTerminal> {
+        self.terminals.iter_mut().find(|terminal| terminal.buffer_id == buffer_id)
+    }
+
+    /// Updates every terminal buffer and forgets the terminals whose job has
+    /// exited.
+    pub fn poll(&mut self) {
+        self.terminals.retain_mut(|terminal| terminal.poll());
+    }
+
+    /// Forwards `key` to the job of the current buffer. Returns false when the
+    /// current buffer is not a running terminal, so the key should go to
+    /// libvim instead.
+    pub fn key(&mut self, key: Key) -> bool {
+        let buffer_id = unsafe { vimBufferGetId(vimBufferGetCurrent()) };
+        match self.get_mut(buffer_id) {
+            Some(terminal) if terminal.is_running() => terminal.send_key(key).is_ok(),
+            _ => false
+        }
+    }
+}
+
+impl Drop for PtyTerminals {
+    fn drop(&mut self) {
+        self.detach();
+    }
+}
+
+fn open_pty(rows: c_int, cols: c_int) -> io::Result<(File, File)> {
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if master < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = File::from_raw_fd(master);
+        if libc::grantpt(master.as_raw_fd()) < 0 || libc::unlockpt(master.as_raw_fd()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let name = libc::ptsname(master.as_raw_fd());
+        if name.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let path = CStr::from_ptr(name).to_string_lossy().into_owned();
+        let slave = OpenOptions::new().read(true).write(true).open(path)?;
+        set_window_size(master.as_raw_fd(), rows, cols)?;
+        Ok((master, slave))
+    }
+}
+
+fn set_window_size(fd: c_int, rows: c_int, cols: c_int) -> io::Result<()> {
+    let size = libc::winsize {
+        ws_row: rows.max(1) as u16,
+        ws_col: cols.max(1) as u16,
+        ws_xpixel: 0,
+        ws_ypixel: 0
+    };
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// The bytes a terminal sends for `key`, with Alt as an escape prefix.
+fn key_bytes(key: Key) -> Vec<u8> {
+    let mut bytes = if key.modifiers.alt { vec![0x1b] } else { Vec::new() };
+    let sequence: &[u8] = match key.code {
+        KeyCode::Char(c) if key.modifiers.control && (c.is_ascii_alphabetic() || "@[\\]^_ ".contains(c)) => {
+            bytes.push(c.to_ascii_uppercase() as u8 & 0x1f);
+            return bytes;
+        },
+        KeyCode::Char(c) => {
+            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            return bytes;
+        },
+        KeyCode::Enter => b"\r",
+        KeyCode::Esc => b"\x1b",
+        KeyCode::Tab if key.modifiers.shift => b"\x1b[Z",
+        KeyCode::Tab => b"\t",
+        KeyCode::Backspace => b"\x7f",
+        KeyCode::Delete => b"\x1b[3~",
+        KeyCode::Insert => b"\x1b[2~",
+        KeyCode::Up => b"\x1b[A",
+        KeyCode::Down => b"\x1b[B",
+        KeyCode::Right => b"\x1b[C",
+        KeyCode::Left => b"\x1b[D",
+        KeyCode::Home => b"\x1b[H",
+        KeyCode::End => b"\x1b[F",
+        KeyCode::PageUp => b"\x1b[5~",
+        KeyCode::PageDown => b"\x1b[6~",
+        KeyCode::Function(1) => b"\x1bOP",
+        KeyCode::Function(2) => b"\x1bOQ",
+        KeyCode::Function(3) => b"\x1bOR",
+        KeyCode::Function(4) => b"\x1bOS",
+        KeyCode::Function(number @ 5..=12) => {
+            let code = [15, 17, 18, 19, 20, 21, 23, 24][number as usize - 5];
+            bytes.extend_from_slice(format!("\x1b[{}~", code).as_bytes());
+            return bytes;
+        },
+        KeyCode::Function(_) => return Vec::new()
+    };
+    bytes.extend_from_slice(sequence);
+    bytes
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+enum EscapeState {
+    #[default]
+    Text,
+    Escape,
+    ControlSequence,
+    String,
+    StringEscape
+}
+
+// Turns raw job output into lines of text: escape sequences and control
+// characters are dropped, a lone carriage return starts the line over and
+// incomplete UTF-8 sequences are held back until the rest arrives.
+#[derive(Debug, Default)]
+struct TerminalOutput {
+    pending: Vec<u8>,
+    state: EscapeState,
+    current: String,
+    carriage_return: bool
+}
+
+impl TerminalOutput {
+    fn feed(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Err(error) if error.error_len().is_none() => error.valid_up_to(),
+            _ => self.pending.len()
+        };
+        let rest = self.pending.split_off(valid);
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending = rest;
+
+        let mut lines = Vec::new();
+        for c in text.chars() {
+            self.state = match (self.state, c) {
+                (EscapeState::Text, '\x1b') => EscapeState::Escape,
+                (EscapeState::Text, _) => {
+                    self.push(c, &mut lines);
+                    EscapeState::Text
+                },
+                (EscapeState::Escape, '[') => EscapeState::ControlSequence,
+                (EscapeState::Escape, ']' | 'P' | '_' | '^') => EscapeState::String,
+                (EscapeState::Escape, _) => EscapeState::Text,
+                (EscapeState::ControlSequence, '\x40'..='\x7e') => EscapeState::Text,
+                (EscapeState::ControlSequence, _) => EscapeState::ControlSequence,
+                (EscapeState::String, '\x07') => EscapeState::Text,
+                (EscapeState::String, '\x1b') => EscapeState::StringEscape,
+                (EscapeState::String, _) => EscapeState::String,
+                (EscapeState::StringEscape, '\\') => EscapeState::Text,
+                (EscapeState::StringEscape, _) => EscapeState::String
+            };
+        }
+        lines
+    }
+
+    fn push(&mut self, c: char, lines: &mut Vec<String>) {
+        match c {
+            '\n' => {
+                lines.push(std::mem::take(&mut self.current));
+                self.carriage_return = false;
+            },
+            '\r' => self.carriage_return = true,
+            '\x08' => {
+                self.current.pop();
+            },
+            '\t' => self.current.push(c),
+            c if c.is_control() => {},
+            c => {
+                if self.carriage_return {
+                    self.current.clear();
+                    self.carriage_return = false;
+                }
+                self.current.push(c);
+            }
+        }
+    }
+
+    fn current(&self) -> &str {
+        &self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terminal_output_test() {
+        let mut output = TerminalOutput::default();
+        assert_eq!(output.feed(b"\x1b[1;32mok\x1b[0m\r\n\x1b]0;title\x07tests"), vec!["ok".to_string()]);
+        assert_eq!(output.current(), "tests");
+
+        // A carriage return split from its newline still ends the line.
+        assert_eq!(output.feed(b" 10%\r"), Vec::<String>::new());
+        assert_eq!(output.feed(b"\n"), vec!["tests 10%".to_string()]);
+
+        // Progress output redraws the line in place.
+        output.feed(b" 50%\r100%");
+        assert_eq!(output.current(), "100%");
+
+        // Multi-byte characters can arrive in pieces.
+        let bytes = "\r\ncaf\u{e9}".as_bytes();
+        output.feed(&bytes[..6]);
+        assert_eq!(output.current(), "caf");
+        output.feed(&bytes[6..]);
+        assert_eq!(output.current(), "caf\u{e9}");
+
+    }
+
+    #[test]
+    fn key_bytes_test() {
+        let bytes: Vec<u8> = Key::parse_sequence("ls<CR>").into_iter().flat_map(key_bytes).collect();
+        assert_eq!(bytes, b"ls\r");
+        assert_eq!(key_bytes(Key::ctrl('c')), vec![3]);
+        assert_eq!(key_bytes("<Up>".parse().unwrap()), b"\x1b[A");
+        assert_eq!(key_bytes("<M-b>".parse().unwrap()), b"\x1bb");
+        assert_eq!(key_bytes("<S-Tab>".parse().unwrap()), b"\x1b[Z");
+        assert_eq!(key_bytes("<F5>".parse().unwrap()), b"\x1b[15~");
+        assert_eq!(key_bytes(Key::char('\u{e9}')), "\u{e9}".as_bytes());
+    }
+}
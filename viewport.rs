@@ -0,0 +1,114 @@
+use libc::{ c_int, c_long };
+
+use crate::event::{ emit, VimEvent };
+use crate::{
+    scrollDirection_T, scrollDirection_T_SCROLL_COLUMN_LEFT, scrollDirection_T_SCROLL_COLUMN_RIGHT,
+    scrollDirection_T_SCROLL_CURSOR_BOTTOM, scrollDirection_T_SCROLL_CURSOR_CENTERH,
+    scrollDirection_T_SCROLL_CURSOR_CENTERV, scrollDirection_T_SCROLL_CURSOR_LEFT,
+    scrollDirection_T_SCROLL_CURSOR_RIGHT, scrollDirection_T_SCROLL_CURSOR_TOP,
+    scrollDirection_T_SCROLL_HALFPAGE_DOWN, scrollDirection_T_SCROLL_HALFPAGE_LEFT,
+    scrollDirection_T_SCROLL_HALFPAGE_RIGHT, scrollDirection_T_SCROLL_HALFPAGE_UP,
+    scrollDirection_T_SCROLL_LINE_DOWN, scrollDirection_T_SCROLL_LINE_UP,
+    scrollDirection_T_SCROLL_PAGE_DOWN, scrollDirection_T_SCROLL_PAGE_UP, vimSetScrollCallback,
+    vimWindowGetHeight, vimWindowGetLeftColumn, vimWindowGetTopLine, vimWindowGetWidth,
+    vimWindowSetHeight, vimWindowSetTopLeft, vimWindowSetWidth
+};
+
+/// The part of the current buffer libvim considers visible. `top_line` is a
+/// line number, `left_column` the first visible column when not wrapping.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Viewport {
+    pub top_line: c_int,
+    pub left_column: c_int,
+    pub width: c_int,
+    pub height: c_int
+}
+
+pub fn vim_get_viewport() -> Viewport {
+    unsafe {
+        Viewport {
+            top_line: vimWindowGetTopLine(),
+            left_column: vimWindowGetLeftColumn(),
+            width: vimWindowGetWidth(),
+            height: vimWindowGetHeight()
+        }
+    }
+}
+
+pub fn vim_set_viewport(viewport: Viewport) {
+    unsafe {
+        vimWindowSetWidth(viewport.width);
+        vimWindowSetHeight(viewport.height);
+        vimWindowSetTopLeft(viewport.top_line, viewport.left_column);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScrollDirection {
+    /// `zz`
+    CursorCenterVertically,
+    /// Centers the cursor column horizontally.
+    CursorCenterHorizontally,
+    /// `zt`
+    CursorTop,
+    /// `zb`
+    CursorBottom,
+    /// `zs`
+    CursorLeft,
+    /// `ze`
+    CursorRight,
+    /// `<C-y>`
+    LineUp,
+    /// `<C-e>`
+    LineDown,
+    /// `<C-d>`
+    HalfPageDown,
+    /// `<C-u>`
+    HalfPageUp,
+    /// `<C-f>`
+    PageDown,
+    /// `<C-b>`
+    PageUp,
+    /// `zH`
+    HalfPageLeft,
+    /// `zL`
+    HalfPageRight,
+    /// `zh`
+    ColumnLeft,
+    /// `zl`
+    ColumnRight
+}
+
+impl ScrollDirection {
+    fn from_raw(raw: scrollDirection_T) -> Option<ScrollDirection> {
+        let directions = [
+            (scrollDirection_T_SCROLL_CURSOR_CENTERV, ScrollDirection::CursorCenterVertically),
+            (scrollDirection_T_SCROLL_CURSOR_CENTERH, ScrollDirection::CursorCenterHorizontally),
+            (scrollDirection_T_SCROLL_CURSOR_TOP, ScrollDirection::CursorTop),
+            (scrollDirection_T_SCROLL_CURSOR_BOTTOM, ScrollDirection::CursorBottom),
+            (scrollDirection_T_SCROLL_CURSOR_LEFT, ScrollDirection::CursorLeft),
+            (scrollDirection_T_SCROLL_CURSOR_RIGHT, ScrollDirection::CursorRight),
+            (scrollDirection_T_SCROLL_LINE_UP, ScrollDirection::LineUp),
+            (scrollDirection_T_SCROLL_LINE_DOWN, ScrollDirection::LineDown),
+            (scrollDirection_T_SCROLL_HALFPAGE_DOWN, ScrollDirection::HalfPageDown),
+            (scrollDirection_T_SCROLL_HALFPAGE_UP, ScrollDirection::HalfPageUp),
+            (scrollDirection_T_SCROLL_PAGE_DOWN, ScrollDirection::PageDown),
+            (scrollDirection_T_SCROLL_PAGE_UP, ScrollDirection::PageUp),
+            (scrollDirection_T_SCROLL_HALFPAGE_LEFT, ScrollDirection::HalfPageLeft),
+            (scrollDirection_T_SCROLL_HALFPAGE_RIGHT, ScrollDirection::HalfPageRight),
+            (scrollDirection_T_SCROLL_COLUMN_LEFT, ScrollDirection::ColumnLeft),
+            (scrollDirection_T_SCROLL_COLUMN_RIGHT, ScrollDirection::ColumnRight)
+        ];
+        directions.iter().find(|(raw_direction, _)| *raw_direction == raw).map(|(_, direction)| *direction)
+    }
+}
+
+pub(crate) fn install_callbacks() {
+    unsafe { vimSetScrollCallback(Some(scroll)); }
+}
+
+unsafe extern "C" fn scroll(dir: scrollDirection_T, count: c_long) {
+    if let Some(direction) = ScrollDirection::from_raw(dir) {
+        emit(VimEvent::Scroll { direction, count });
+    }
+}
@@ -0,0 +1,116 @@
+use libc::c_int;
+
+use crate::event::{ emit, VimEvent };
+use crate::{
+    char_u, string_from_vim, vimSetWindowMovementCallback, vimSetWindowSplitCallback,
+    windowMovement_T, windowMovement_T_WIN_CURSOR_BOTTOM_RIGHT, windowMovement_T_WIN_CURSOR_DOWN,
+    windowMovement_T_WIN_CURSOR_LEFT, windowMovement_T_WIN_CURSOR_PREVIOUS,
+    windowMovement_T_WIN_CURSOR_RIGHT, windowMovement_T_WIN_CURSOR_TOP_LEFT,
+    windowMovement_T_WIN_CURSOR_UP, windowMovement_T_WIN_MOVE_FULL_DOWN,
+    windowMovement_T_WIN_MOVE_FULL_LEFT, windowMovement_T_WIN_MOVE_FULL_RIGHT,
+    windowMovement_T_WIN_MOVE_FULL_UP, windowMovement_T_WIN_MOVE_ROTATE_DOWNWARDS,
+    windowMovement_T_WIN_MOVE_ROTATE_UPWARDS, windowSplit_T, windowSplit_T_SPLIT_HORIZONTAL,
+    windowSplit_T_SPLIT_HORIZONTAL_NEW, windowSplit_T_SPLIT_TAB, windowSplit_T_SPLIT_TAB_NEW,
+    windowSplit_T_SPLIT_VERTICAL, windowSplit_T_SPLIT_VERTICAL_NEW
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SplitKind {
+    /// `:split`, `<C-w>s`
+    Horizontal,
+    /// `:new`, `<C-w>n`
+    HorizontalNew,
+    /// `:vsplit`, `<C-w>v`
+    Vertical,
+    /// `:vnew`
+    VerticalNew,
+    /// `:tabedit {file}`
+    Tab,
+    /// `:tabnew`
+    TabNew
+}
+
+impl SplitKind {
+    fn from_raw(raw: windowSplit_T) -> Option<SplitKind> {
+        let kinds = [
+            (windowSplit_T_SPLIT_HORIZONTAL, SplitKind::Horizontal),
+            (windowSplit_T_SPLIT_HORIZONTAL_NEW, SplitKind::HorizontalNew),
+            (windowSplit_T_SPLIT_VERTICAL, SplitKind::Vertical),
+            (windowSplit_T_SPLIT_VERTICAL_NEW, SplitKind::VerticalNew),
+            (windowSplit_T_SPLIT_TAB, SplitKind::Tab),
+            (windowSplit_T_SPLIT_TAB_NEW, SplitKind::TabNew)
+        ];
+        kinds.iter().find(|(raw_kind, _)| *raw_kind == raw).map(|(_, kind)| *kind)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WindowMove {
+    /// `<C-w>h`
+    CursorLeft,
+    /// `<C-w>l`
+    CursorRight,
+    /// `<C-w>k`
+    CursorUp,
+    /// `<C-w>j`
+    CursorDown,
+    /// `<C-w>H`
+    MoveFullLeft,
+    /// `<C-w>L`
+    MoveFullRight,
+    /// `<C-w>K`
+    MoveFullUp,
+    /// `<C-w>J`
+    MoveFullDown,
+    /// `<C-w>t`
+    CursorTopLeft,
+    /// `<C-w>b`
+    CursorBottomRight,
+    /// `<C-w>p`
+    CursorPrevious,
+    /// `<C-w>r`
+    RotateDownwards,
+    /// `<C-w>R`
+    RotateUpwards
+}
+
+impl WindowMove {
+    fn from_raw(raw: windowMovement_T) -> Option<WindowMove> {
+        let movements = [
+            (windowMovement_T_WIN_CURSOR_LEFT, WindowMove::CursorLeft),
+            (windowMovement_T_WIN_CURSOR_RIGHT, WindowMove::CursorRight),
+            (windowMovement_T_WIN_CURSOR_UP, WindowMove::CursorUp),
+            (windowMovement_T_WIN_CURSOR_DOWN, WindowMove::CursorDown),
+            (windowMovement_T_WIN_MOVE_FULL_LEFT, WindowMove::MoveFullLeft),
+            (windowMovement_T_WIN_MOVE_FULL_RIGHT, WindowMove::MoveFullRight),
+            (windowMovement_T_WIN_MOVE_FULL_UP, WindowMove::MoveFullUp),
+            (windowMovement_T_WIN_MOVE_FULL_DOWN, WindowMove::MoveFullDown),
+            (windowMovement_T_WIN_CURSOR_TOP_LEFT, WindowMove::CursorTopLeft),
+            (windowMovement_T_WIN_CURSOR_BOTTOM_RIGHT, WindowMove::CursorBottomRight),
+            (windowMovement_T_WIN_CURSOR_PREVIOUS, WindowMove::CursorPrevious),
+            (windowMovement_T_WIN_MOVE_ROTATE_DOWNWARDS, WindowMove::RotateDownwards),
+            (windowMovement_T_WIN_MOVE_ROTATE_UPWARDS, WindowMove::RotateUpwards)
+        ];
+        movements.iter().find(|(raw_movement, _)| *raw_movement == raw).map(|(_, movement)| *movement)
+    }
+}
+
+pub(crate) fn install_callbacks() {
+    unsafe {
+        vimSetWindowSplitCallback(Some(window_split));
+        vimSetWindowMovementCallback(Some(window_movement));
+    }
+}
+
+unsafe extern "C" fn window_split(split_type: windowSplit_T, fname: *mut char_u) {
+    if let Some(kind) = SplitKind::from_raw(split_type) {
+        let filename = string_from_vim(fname).filter(|filename| !filename.is_empty());
+        emit(VimEvent::WindowSplit { kind, filename });
+    }
+}
+
+unsafe extern "C" fn window_movement(movement_type: windowMovement_T, count: c_int) {
+    if let Some(movement) = WindowMove::from_raw(movement_type) {
+        emit(VimEvent::WindowMoved { movement, count });
+    }
+}